@@ -0,0 +1,215 @@
+use serde::Deserialize;
+use std::fs::{self, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::watch::Receiver;
+
+use crate::config::{ApiConfig, PrivacyConfig};
+use crate::status::Status;
+use crate::watcher::ActiveJobs;
+
+/// one request per line, e.g. `{"request": "get_status"}`
+#[derive(Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum Request {
+    /// answer with the current status once
+    GetStatus,
+
+    /// answer with the current status and again after every change
+    Subscribe,
+}
+
+/// serves the watcher state as JSON on a unix socket
+/// so status bars don't need to walk /proc themselves
+pub(crate) struct StatusServer {
+    /// socket path
+    path: PathBuf,
+
+    /// bound on creation so a second instance fails right away
+    listener: UnixListener,
+
+    /// drop rules applied before answering
+    privacy: Arc<PrivacyConfig>,
+
+    /// watcher updates
+    rx: Receiver<ActiveJobs>,
+}
+
+impl StatusServer {
+    /// create the socket, clients are served on run
+    pub(crate) fn bind(
+        config: &ApiConfig,
+        privacy: PrivacyConfig,
+        rx: Receiver<ActiveJobs>,
+    ) -> Result<Self, String> {
+        let path = &config.socket_path();
+        remove_stale_socket(path)?;
+
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Could not bind {}: {}", path.display(), e))?;
+
+        fs::set_permissions(path, Permissions::from_mode(config.mode))
+            .map_err(|e| format!("Could not set permissions of {}: {}", path.display(), e))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+            privacy: Arc::new(privacy),
+            rx,
+        })
+    }
+
+    /// accept clients until the listener fails
+    pub(crate) async fn run(self) -> Result<(), String> {
+        #[cfg(debug_assertions)]
+        println!("Serving status on {}", self.path.display());
+
+        loop {
+            let (stream, _) = self
+                .listener
+                .accept()
+                .await
+                .map_err(|e| format!("Error accepting status client: {}", e))?;
+
+            let rx = self.rx.clone();
+            let privacy = self.privacy.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_client(stream, rx, &privacy).await {
+                    eprintln!("Error serving status client: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// remove a socket left behind by an instance that didn't shut down cleanly
+/// anything else at the path, or a socket somebody still listens on, is an error
+pub(crate) fn remove_stale_socket(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => (),
+        Ok(_) => return Err(format!("{} exists and is not a socket", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Could not check {}: {}", path.display(), e)),
+    }
+
+    match net::UnixStream::connect(path) {
+        Ok(_) => Err(format!(
+            "{} is in use, is portpresence already running?",
+            path.display()
+        )),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => fs::remove_file(path)
+            .map_err(|e| format!("Could not remove stale {}: {}", path.display(), e)),
+        Err(e) => Err(format!("Could not check {}: {}", path.display(), e)),
+    }
+}
+
+/// answer requests until the client disconnects
+async fn handle_client(
+    stream: UnixStream,
+    mut rx: Receiver<ActiveJobs>,
+    privacy: &PrivacyConfig,
+) -> Result<(), String> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = serde_json::json!({ "error": format!("Invalid request: {}", e) });
+                send_line(&mut write, error.to_string()).await?;
+                continue;
+            }
+        };
+
+        match request {
+            Request::GetStatus => {
+                let status = status_line(&privacy.apply(&rx.borrow()))?;
+                send_line(&mut write, status).await?;
+            }
+            Request::Subscribe => loop {
+                let status = status_line(&privacy.apply(&rx.borrow_and_update()))?;
+                send_line(&mut write, status).await?;
+
+                // further requests just resend the status
+                tokio::select! {
+                    changed = rx.changed() => {
+                        if changed.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    line = lines.next_line() => {
+                        if !matches!(line, Ok(Some(_))) {
+                            return Ok(());
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// serialize the current state
+fn status_line(job_trees: &ActiveJobs) -> Result<String, String> {
+    serde_json::to_string(&Status::new(job_trees)).map_err(|e| e.to_string())
+}
+
+/// write a line and terminate it
+async fn send_line(write: &mut OwnedWriteHalf, mut line: String) -> Result<(), String> {
+    line.push('\n');
+    write
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("portpresence-{}-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn stale_socket() {
+        let path = socket_path("stale");
+        let _ = fs::remove_file(&path);
+        assert!(remove_stale_socket(&path).is_ok());
+
+        // the file stays behind when the listener is gone
+        drop(net::UnixListener::bind(&path).unwrap());
+        assert!(remove_stale_socket(&path).is_ok());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn socket_in_use() {
+        let path = socket_path("in-use");
+        let _ = fs::remove_file(&path);
+        let listener = net::UnixListener::bind(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_a_socket() {
+        let path = socket_path("file");
+        fs::write(&path, "").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// version suffix kinds in PMS order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SuffixKind {
+    Alpha,
+    Beta,
+    Pre,
+    Rc,
+    P,
+}
+
+impl SuffixKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            SuffixKind::Alpha => "alpha",
+            SuffixKind::Beta => "beta",
+            SuffixKind::Pre => "pre",
+            SuffixKind::Rc => "rc",
+            SuffixKind::P => "p",
+        }
+    }
+}
+
+/// version suffix like `_rc2` or `_p`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Suffix {
+    pub(crate) kind: SuffixKind,
+
+    /// number after the suffix, kept as string to preserve leading zeros
+    pub(crate) number: Option<String>,
+}
+
+/// package version as defined in PMS section 3.2
+/// e.g. `3.12.4_p1-r2`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Version {
+    /// dot separated numeric components, kept as strings to preserve leading zeros
+    pub(crate) numbers: Vec<String>,
+
+    /// optional single letter after the last number
+    pub(crate) letter: Option<char>,
+
+    /// `_alpha`, `_beta`, `_pre`, `_rc` and `_p` suffixes
+    pub(crate) suffixes: Vec<Suffix>,
+
+    /// `-rN` revision, None if not given
+    pub(crate) revision: Option<String>,
+}
+
+impl Version {
+    /// version without revision, i.e. PV
+    pub(crate) fn pv(&self) -> String {
+        let mut pv = self.numbers.join(".");
+        if let Some(letter) = self.letter {
+            pv.push(letter);
+        }
+        for suffix in &self.suffixes {
+            pv.push('_');
+            pv.push_str(suffix.kind.as_str());
+            if let Some(ref number) = suffix.number {
+                pv.push_str(number);
+            }
+        }
+        pv
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid version \"{}\"", s);
+
+        let (pv, revision) = match s.rsplit_once("-r") {
+            Some((pv, rev)) if is_number(rev) => (pv, Some(String::from(rev))),
+            _ => (s, None),
+        };
+
+        let mut parts = pv.split('_');
+        let base = parts.next().ok_or_else(invalid)?;
+
+        let (base, letter) = match base.chars().last() {
+            Some(c) if c.is_ascii_lowercase() => (&base[..base.len() - 1], Some(c)),
+            _ => (base, None),
+        };
+
+        let numbers: Vec<String> = base.split('.').map(String::from).collect();
+        if !numbers.iter().all(|n| is_number(n)) {
+            return Err(invalid());
+        }
+
+        let mut suffixes = Vec::new();
+        for part in parts {
+            let split = part
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(part.len());
+            let (kind, number) = part.split_at(split);
+            let kind = match kind {
+                "alpha" => SuffixKind::Alpha,
+                "beta" => SuffixKind::Beta,
+                "pre" => SuffixKind::Pre,
+                "rc" => SuffixKind::Rc,
+                "p" => SuffixKind::P,
+                _ => return Err(invalid()),
+            };
+            let number = match number {
+                "" => None,
+                n if is_number(n) => Some(String::from(n)),
+                _ => return Err(invalid()),
+            };
+            suffixes.push(Suffix { kind, number });
+        }
+
+        Ok(Self {
+            numbers,
+            letter,
+            suffixes,
+            revision,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pv())?;
+        if let Some(ref revision) = self.revision {
+            write!(f, "-r{}", revision)?;
+        }
+        Ok(())
+    }
+}
+
+/// category/package-version as used in portage build dirs
+/// e.g. `dev-lang/python-3.12.4_p1-r2`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Atom {
+    pub(crate) category: String,
+    pub(crate) package: String,
+    pub(crate) version: Version,
+}
+
+impl FromStr for Atom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (category, pf) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Missing category in \"{}\"", s))?;
+
+        if !is_valid_name(category, "+_.-") {
+            return Err(format!("Invalid category \"{}\"", category));
+        }
+
+        // the package name must not end in a hyphen followed by
+        // something that looks like a version, so the version
+        // starts after the first hyphen where the rest is valid
+        for (i, _) in pf.match_indices('-') {
+            let (package, version) = (&pf[..i], &pf[i + 1..]);
+            let Ok(version) = version.parse::<Version>() else {
+                continue;
+            };
+            if !is_valid_name(package, "+_-") {
+                return Err(format!("Invalid package name \"{}\"", package));
+            }
+            return Ok(Self {
+                category: String::from(category),
+                package: String::from(package),
+                version,
+            });
+        }
+
+        Err(format!("Missing version in \"{}\"", s))
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}-{}", self.category, self.package, self.version)
+    }
+}
+
+/// non-empty and ASCII digits only
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// category and package names may only contain alphanumerics and `extra`
+/// and must not start with a hyphen
+fn is_valid_name(s: &str, extra: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(['-', '+', '.'])
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || extra.contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str) -> Atom {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn simple() {
+        let a = atom("sys-kernel/cachyos-kernel-6.15.1");
+        assert_eq!(a.category, "sys-kernel");
+        assert_eq!(a.package, "cachyos-kernel");
+        assert_eq!(a.version.numbers, ["6", "15", "1"]);
+        assert_eq!(a.version.revision, None);
+    }
+
+    #[test]
+    fn suffix_and_revision() {
+        let a = atom("dev-lang/python-3.12.4_p1-r2");
+        assert_eq!(a.package, "python");
+        assert_eq!(a.version.pv(), "3.12.4_p1");
+        assert_eq!(a.version.revision.as_deref(), Some("2"));
+        assert_eq!(
+            a.version.suffixes,
+            [Suffix {
+                kind: SuffixKind::P,
+                number: Some(String::from("1")),
+            }]
+        );
+    }
+
+    #[test]
+    fn multiple_suffixes() {
+        let v = atom("app-misc/foo-1.0_alpha_pre20240101_rc").version;
+        let kinds: Vec<SuffixKind> = v.suffixes.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds, [SuffixKind::Alpha, SuffixKind::Pre, SuffixKind::Rc]);
+        assert_eq!(v.suffixes[1].number.as_deref(), Some("20240101"));
+        assert_eq!(v.suffixes[2].number, None);
+    }
+
+    #[test]
+    fn letter_suffix() {
+        let v = atom("dev-libs/openssl-1.1.1w").version;
+        assert_eq!(v.numbers, ["1", "1", "1"]);
+        assert_eq!(v.letter, Some('w'));
+    }
+
+    #[test]
+    fn live_version() {
+        let a = atom("sys-libs/libcxx-abi-9999");
+        assert_eq!(a.package, "libcxx-abi");
+        assert_eq!(a.version.to_string(), "9999");
+    }
+
+    #[test]
+    fn name_segment_starting_with_digit() {
+        let a = atom("media-fonts/font-adobe-100dpi-1.0.4");
+        assert_eq!(a.package, "font-adobe-100dpi");
+        assert_eq!(a.version.to_string(), "1.0.4");
+
+        let a = atom("dev-perl/Locale-gettext-1.70.0-r1");
+        assert_eq!(a.package, "Locale-gettext");
+        assert_eq!(a.version.pv(), "1.70.0");
+    }
+
+    #[test]
+    fn name_with_plus_and_digits() {
+        let a = atom("sys-libs/libstdc++-v3-3.3.6-r2");
+        assert_eq!(a.package, "libstdc++-v3");
+        assert_eq!(a.version.to_string(), "3.3.6-r2");
+
+        let a = atom("x11-libs/gtk+-2.24.33-r3");
+        assert_eq!(a.package, "gtk+");
+
+        let a = atom("dev-qt/qt5compat-6.7.2");
+        assert_eq!(a.package, "qt5compat");
+    }
+
+    #[test]
+    fn name_looks_like_revision() {
+        // `-r1` alone is not a version
+        let a = atom("app-misc/foo-r1-2.0");
+        assert_eq!(a.package, "foo-r1");
+        assert_eq!(a.version.to_string(), "2.0");
+    }
+
+    #[test]
+    fn leading_zeros_roundtrip() {
+        for s in [
+            "net-misc/openssh-9.8_p1-r1",
+            "app-arch/unzip-6.0_p27-r1",
+            "media-libs/libpng-1.6.043",
+            "dev-util/foo-2024.01.05_beta03-r10",
+            "app-editors/vim-9.1.0707",
+        ] {
+            assert_eq!(atom(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "python-3.12",
+            "dev-lang/python",
+            "dev-lang/python-3.12_foo",
+            "dev-lang/python-3..12",
+            "dev-lang/-1.0",
+            "dev-lang/python-1.0ab",
+            "/python-1.0",
+        ] {
+            assert!(s.parse::<Atom>().is_err(), "{} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::build_progress::parse_progress;
+use crate::phase::{Phase, parse_failure};
+
+/// most bytes read from the end of the log at once
+/// the last line is all we want, so skip whatever came before
+const MAX_TAIL: u64 = 8192;
+
+/// follows temp/build.log in the build dir of a job
+#[derive(Clone, Debug)]
+pub(crate) struct BuildLog {
+    /// log file
+    pub(crate) path: PathBuf,
+
+    /// read position, after the last complete line
+    offset: u64,
+
+    /// last line worth showing so far
+    last_line: Option<String>,
+
+    /// last percentage the build system reported
+    progress: Option<u8>,
+
+    /// phase portage reported as failed
+    failure: Option<Phase>,
+}
+
+impl BuildLog {
+    /// follow the log in a build dir like /var/tmp/portage/dev-lang/rust-1.86.0
+    /// nothing is read until `sample`
+    pub(crate) fn new(build_dir: &Path) -> Self {
+        Self {
+            path: build_dir.join("temp").join("build.log"),
+            offset: 0,
+            last_line: None,
+            progress: None,
+            failure: None,
+        }
+    }
+
+    /// last line worth showing, like the current `[345/1203]` ninja step
+    pub(crate) fn last_line(&self) -> Option<&str> {
+        self.last_line.as_deref()
+    }
+
+    /// percentage of the last progress line, like 28 for `[345/1203]`
+    pub(crate) fn progress(&self) -> Option<u8> {
+        self.progress
+    }
+
+    /// phase that died, from ` * ERROR: cat/pkg-ver::repo failed (compile phase):`
+    pub(crate) fn failure(&self) -> Option<&Phase> {
+        self.failure.as_ref()
+    }
+
+    /// read lines appended since the last call
+    /// returns whether the last line or progress changed
+    pub(crate) fn sample(&mut self) -> bool {
+        let Ok(mut file) = File::open(&self.path) else {
+            return false; // no log (yet) or not readable
+        };
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return false,
+        };
+
+        // start over if the log was truncated, e.g. by a new build
+        if len < self.offset {
+            self.offset = 0;
+        }
+        if len == self.offset {
+            return false;
+        }
+
+        let start = self.offset.max(len.saturating_sub(MAX_TAIL));
+        if file.seek(SeekFrom::Start(start)).is_err() {
+            return false;
+        }
+        let mut chunk = Vec::new();
+        if file.take(len - start).read_to_end(&mut chunk).is_err() {
+            return false;
+        }
+
+        // the build is still writing the last line, read it next time
+        let Some(end) = chunk.iter().rposition(|b| *b == b'\n') else {
+            return false;
+        };
+        self.offset = start + end as u64 + 1;
+
+        let mut text = String::from_utf8_lossy(&chunk[..end]).into_owned();
+        // skipped into the middle of a line
+        if start > 0
+            && let Some(first) = text.find('\n')
+        {
+            text.drain(..=first);
+        }
+
+        let mut changed = false;
+        if let Some(line) = last_meaningful_line(&text)
+            && self.last_line.as_ref() != Some(&line)
+        {
+            self.last_line = Some(line);
+            changed = true;
+        }
+        // warnings are often printed between progress lines
+        if let Some(progress) = last_progress(&text)
+            && self.progress != Some(progress)
+        {
+            self.progress = Some(progress);
+            changed = true;
+        }
+        // only matters once the job exits
+        if let Some(phase) = first_failure(&text) {
+            self.failure = Some(phase);
+        }
+        changed
+    }
+}
+
+/// repository of the package in a build dir, for when the job environment isn't readable
+/// build-info is written after src_install, temp/environment is saved after each phase
+pub(crate) fn build_repository(build_dir: &Path) -> Option<String> {
+    if let Ok(repository) = fs::read_to_string(build_dir.join("build-info").join("repository"))
+        && !repository.trim().is_empty()
+    {
+        return Some(String::from(repository.trim()));
+    }
+    let environment = fs::read_to_string(build_dir.join("temp").join("environment")).ok()?;
+    environment
+        .lines()
+        .find_map(|line| saved_variable(line, "PORTAGE_REPO_NAME"))
+        .map(String::from)
+}
+
+/// whether the build dir belongs to a binary package
+/// portage records the checksum of the binpkg it unpacked the metadata from
+pub(crate) fn is_binary_build(build_dir: &Path) -> bool {
+    build_dir.join("build-info").join("BINPKGMD5").exists()
+}
+
+/// value of a `declare -x NAME="value"` line as bash saves the environment
+fn saved_variable<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (_, assignment) = line.strip_prefix("declare ")?.split_once(' ')?;
+    let value = assignment.strip_prefix(name)?.strip_prefix('=')?;
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    Some(value).filter(|value| !value.is_empty())
+}
+
+/// last line with something to read in it, without colors
+/// progress bars redraw with `\r`, so that ends a line too
+fn last_meaningful_line(text: &str) -> Option<String> {
+    text.split(['\n', '\r'])
+        .rev()
+        .map(strip_ansi)
+        .map(|line| String::from(line.trim()))
+        .find(|line| line.chars().any(char::is_alphanumeric))
+}
+
+/// percentage of the last line reporting progress
+fn last_progress(text: &str) -> Option<u8> {
+    text.split(['\n', '\r'])
+        .rev()
+        .find_map(|line| parse_progress(&strip_ansi(line)))
+}
+
+/// phase of the first `ERROR: ... failed (... phase):` line
+/// eerror colors the star, so strip that first
+fn first_failure(text: &str) -> Option<Phase> {
+    text.lines()
+        .find_map(|line| parse_failure(&strip_ansi(line)).map(|(_, _, phase)| phase))
+}
+
+/// remove terminal escape sequences like `\x1b[32m`
+fn strip_ansi(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI sequences end with a byte in @..~, others are a single char
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_line() {
+        let log = "[340/1203] Building CXX object foo.o\n[341/1203] Building CXX object bar.o\n\n";
+        assert_eq!(
+            last_meaningful_line(log).as_deref(),
+            Some("[341/1203] Building CXX object bar.o")
+        );
+    }
+
+    #[test]
+    fn skips_noise() {
+        let log = "make[2]: Entering directory '/x'\n   \n----\n";
+        assert_eq!(
+            last_meaningful_line(log).as_deref(),
+            Some("make[2]: Entering directory '/x'")
+        );
+        assert_eq!(last_meaningful_line("\n \n==\n"), None);
+    }
+
+    #[test]
+    fn colors_and_progress_bars() {
+        assert_eq!(
+            strip_ansi("\x1b[1m\x1b[32m   Compiling\x1b[0m serde v1.0.219"),
+            "   Compiling serde v1.0.219"
+        );
+        assert_eq!(
+            last_meaningful_line("Downloading 10%\rDownloading 55%\r").as_deref(),
+            Some("Downloading 55%")
+        );
+    }
+
+    #[test]
+    fn progress_between_warnings() {
+        let log = "[12/40] Building C object a.o\n\x1b[1m[13/40] Building C object b.o\x1b[0m\nb.c:3:5: warning: unused variable 'x'\n";
+        assert_eq!(last_progress(log), Some(32));
+        assert_eq!(last_progress("configure: creating Makefile\n"), None);
+    }
+
+    #[test]
+    fn environment() {
+        assert_eq!(
+            saved_variable(
+                "declare -x PORTAGE_REPO_NAME=\"acme-overlay\"",
+                "PORTAGE_REPO_NAME"
+            ),
+            Some("acme-overlay")
+        );
+        assert_eq!(
+            saved_variable("declare -- PORTAGE_REPO_NAME=gentoo", "PORTAGE_REPO_NAME"),
+            Some("gentoo")
+        );
+        assert_eq!(
+            saved_variable("declare -x PORTAGE_REPO_NAMES=\"x\"", "PORTAGE_REPO_NAME"),
+            None
+        );
+        assert_eq!(
+            saved_variable("declare -x PORTAGE_REPO_NAME=\"\"", "PORTAGE_REPO_NAME"),
+            None
+        );
+        assert_eq!(
+            saved_variable("PORTAGE_REPO_NAME=gentoo", "PORTAGE_REPO_NAME"),
+            None
+        );
+    }
+
+    #[test]
+    fn colored_failure() {
+        let log = "\x1b[33;01m*\x1b[0m QA Notice: foo\n\x1b[31;01m*\x1b[0m ERROR: dev-libs/foo-1.0::gentoo failed (compile phase):\n\x1b[31;01m*\x1b[0m   emake failed\n";
+        assert_eq!(first_failure(log), Some(Phase::Compile));
+        assert_eq!(first_failure(" * Applying foo-1.0-fix.patch ...\n"), None);
+    }
+}
@@ -0,0 +1,181 @@
+/// percentage a build log line reports, recognizes
+/// ninja and meson `[345/1203] Building CXX object ...`,
+/// CMake makefiles `[ 45%] Building C object ...`,
+/// meson tests ` 3/12 foo:test_bar      OK      0.01s`,
+/// cargo `Compiling serde v1.0.219 (45/120)`
+/// and its progress bar `Building [=====>    ] 45/120: serde, ...`
+pub(crate) fn parse_progress(line: &str) -> Option<u8> {
+    let line = line.trim_start();
+    if let Some(rest) = line.strip_prefix('[') {
+        let (inside, step) = rest.split_once(']')?;
+        return match inside.strip_suffix('%') {
+            Some(percent) => cmake_percent(percent),
+            None if is_ninja_step(step) => fraction(inside),
+            None => None,
+        };
+    }
+    if let Some(rest) = line.strip_prefix("Compiling ") {
+        let (_, count) = rest.rsplit_once('(')?;
+        return fraction(count.strip_suffix(')')?);
+    }
+    if let Some(rest) = line.strip_prefix("Building [") {
+        let (_, count) = rest.split_once("] ")?;
+        return fraction(count.split(':').next()?);
+    }
+    meson_test(line)
+}
+
+/// what ninja prints after the count, a description like `Building C object ...`
+/// or with `-v`, which the cmake and meson eclasses pass, the command itself
+fn is_ninja_step(step: &str) -> bool {
+    let first = step.split_ascii_whitespace().next().unwrap_or_default();
+    NINJA_STEPS.contains(&first)
+        || first.starts_with('/')
+        || first == "cd"
+        || first == ":"
+        || step.contains(" -o ")
+        || step.contains(" -c ")
+}
+
+/// first words of the step descriptions cmake and meson generate
+const NINJA_STEPS: [&str; 9] = [
+    "Building",
+    "Compiling",
+    "Linking",
+    "Generating",
+    "Copying",
+    "Creating",
+    "Running",
+    "Merging",
+    "Automatic",
+];
+
+/// `45` from CMake, which pads to three columns
+fn cmake_percent(percent: &str) -> Option<u8> {
+    percent
+        .trim()
+        .parse()
+        .ok()
+        .filter(|percent| *percent <= 100)
+}
+
+/// `345/1203` as a percentage, rounded down
+fn fraction(text: &str) -> Option<u8> {
+    let (current, total) = text.trim().split_once('/')?;
+    let current: u64 = current.trim().parse().ok()?;
+    let total: u64 = total.trim().parse().ok()?;
+    if total == 0 || current > total {
+        return None;
+    }
+    Some((current * 100 / total) as u8)
+}
+
+/// meson test results, the count is followed by the test name and result
+/// names can contain spaces like `glib:core / array-test`
+fn meson_test(line: &str) -> Option<u8> {
+    let mut words = line.split_ascii_whitespace();
+    let count = words.next()?;
+    if !words.skip(1).any(|word| MESON_RESULTS.contains(&word)) {
+        return None;
+    }
+    fraction(count)
+}
+
+/// results the meson test runner prints after the test name
+const MESON_RESULTS: [&str; 7] = [
+    "OK",
+    "FAIL",
+    "SKIP",
+    "EXPECTEDFAIL",
+    "UNEXPECTEDPASS",
+    "TIMEOUT",
+    "ERROR",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ninja() {
+        let log = "\
+[1/1203] Generating version.h
+[345/1203] Building CXX object lib/Support/CMakeFiles/LLVMSupport.dir/APInt.cpp.o
+[1203/1203] Linking CXX executable bin/llvm-tblgen";
+        let progress: Vec<_> = log.lines().map(parse_progress).collect();
+        assert_eq!(progress, [Some(0), Some(28), Some(100)]);
+    }
+
+    #[test]
+    fn ninja_verbose() {
+        // the eclasses run ninja with -v, so there is a command instead of a description
+        let log = "\
+[1/4] /usr/bin/x86_64-pc-linux-gnu-g++ -O2 -MD -MT src/foo.cpp.o -o src/foo.cpp.o -c src/foo.cpp
+[2/4] x86_64-pc-linux-gnu-gcc -Isrc/libfoo.so.p -O2 -MD -o src/libfoo.so.p/bar.c.o -c ../src/bar.c
+[3/4] : && /usr/bin/x86_64-pc-linux-gnu-g++ -O2 src/foo.cpp.o -o foo && :
+[4/4] cd /var/tmp/portage/dev-libs/foo-1.0/work/foo-1.0_build && /usr/bin/cmake -E touch stamp";
+        let progress: Vec<_> = log.lines().map(parse_progress).collect();
+        assert_eq!(progress, [Some(25), Some(50), Some(75), Some(100)]);
+    }
+
+    #[test]
+    fn cmake_makefiles() {
+        assert_eq!(
+            parse_progress("[  3%] Building C object src/CMakeFiles/foo.dir/foo.c.o"),
+            Some(3)
+        );
+        assert_eq!(
+            parse_progress("[ 63%] Linking CXX shared library libfoo.so"),
+            Some(63)
+        );
+        assert_eq!(parse_progress("[100%] Built target foo"), Some(100));
+    }
+
+    #[test]
+    fn meson() {
+        // meson compiles with ninja, its test runner has its own format
+        assert_eq!(
+            parse_progress("[12/96] Compiling C object src/libglib.so.p/gstring.c.o"),
+            Some(12)
+        );
+        assert_eq!(
+            parse_progress(" 3/12 glib:core / array-test        OK              0.12s"),
+            Some(25)
+        );
+        assert_eq!(
+            parse_progress(
+                "12/12 glib:core / gvariant           FAIL            1.02s   exit status 1"
+            ),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn cargo() {
+        assert_eq!(
+            parse_progress("   Compiling serde v1.0.219 (45/120)"),
+            Some(37)
+        );
+        assert_eq!(
+            parse_progress("    Building [=====>                  ] 45/120: serde, syn"),
+            Some(37)
+        );
+    }
+
+    #[test]
+    fn not_progress() {
+        for line in [
+            "   Compiling serde v1.0.219",
+            "make[2]: Entering directory '/var/tmp/portage/dev-libs/foo-1.0/work'",
+            "[sys-kernel/gentoo-sources-6.12.1] sandbox",
+            "[5/0] Building nothing",
+            "[1/3] Applying patch foo-1.0-fix.patch",
+            "[2/2] done",
+            "[150%] odd",
+            " * Applying foo-1.0-fix.patch ...",
+            "checking for gcc... 1/2 done",
+        ] {
+            assert_eq!(parse_progress(line), None, "{}", line);
+        }
+    }
+}
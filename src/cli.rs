@@ -0,0 +1,67 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+/// Discord Rich Presence for Portage
+#[derive(Parser)]
+#[command(version, about)]
+pub(crate) struct Cli {
+    /// config file to use instead of the default locations
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub(crate) config: Option<PathBuf>,
+
+    /// override discord.client_id
+    #[arg(long, global = true, value_name = "ID")]
+    pub(crate) client_id: Option<String>,
+
+    /// override watcher.refresh_interval_waiting (seconds)
+    #[arg(long, global = true, value_name = "SECS")]
+    pub(crate) refresh_waiting: Option<u64>,
+
+    /// override watcher.refresh_interval_active (seconds)
+    #[arg(long, global = true, value_name = "SECS")]
+    pub(crate) refresh_active: Option<u64>,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone, Copy)]
+pub(crate) enum Command {
+    /// watch emerge and update the presence (default)
+    Run,
+
+    /// print currently detected jobs once and exit
+    Status,
+
+    /// validate config and check Discord and portage are reachable
+    Check,
+}
+
+impl Cli {
+    /// load the config and apply command line overrides
+    pub(crate) fn load_config(&self) -> Result<Config, String> {
+        let mut config = match self.config {
+            Some(ref path) => Config::from_file(path)?,
+            None => Config::load()?,
+        };
+
+        if let Some(ref client_id) = self.client_id {
+            config.discord.client_id = client_id.clone();
+        }
+        if let Some(secs) = self.refresh_waiting {
+            config.watcher.refresh_interval_waiting = secs;
+        }
+        if let Some(secs) = self.refresh_active {
+            config.watcher.refresh_interval_active = secs;
+        }
+
+        // overrides bypass the file validation
+        config
+            .validate()
+            .map_err(|e| format!("Invalid command line override: {}", e))?;
+
+        Ok(config)
+    }
+}
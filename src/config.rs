@@ -0,0 +1,503 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::phase::Phase;
+use crate::privacy::{AtomPattern, Visibility};
+use crate::template::Template;
+
+/// system wide config file, used if no user config exists
+const SYSTEM_CONFIG_PATH: &str = "/etc/portpresence.toml";
+
+/// user config file relative to the XDG config dir
+const USER_CONFIG_PATH: &str = "portpresence/config.toml";
+
+/// status socket file name in the runtime dir
+const API_SOCKET_NAME: &str = "portpresence.sock";
+
+/// runtime configuration, loaded from TOML
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Discord connection settings
+    pub(crate) discord: DiscordConfig,
+
+    /// process watcher settings
+    pub(crate) watcher: WatcherConfig,
+
+    /// presence text and assets
+    pub(crate) presence: PresenceConfig,
+
+    /// where updates are sent
+    pub(crate) sinks: SinksConfig,
+
+    /// local status socket for status bars and scripts
+    pub(crate) api: ApiConfig,
+
+    /// what the Discord presence may reveal
+    pub(crate) privacy: PrivacyConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DiscordConfig {
+    /// Discord API client ID
+    pub(crate) client_id: String,
+}
+
+impl Default for DiscordConfig {
+    fn default() -> Self {
+        Self {
+            client_id: String::from("1367276666665041960"),
+        }
+    }
+}
+
+/// how the watcher notices new jobs
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Backend {
+    /// rescan all processes every refresh interval
+    Poll,
+
+    /// rescan only after activity in the portage build dirs
+    Inotify,
+
+    /// follow exec/exit events from the kernel proc connector
+    /// needs CAP_NET_ADMIN
+    Netlink,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct WatcherConfig {
+    /// detection backend, falls back to polling if unavailable
+    pub(crate) backend: Backend,
+
+    /// process refresh interval in seconds while no emerge is running
+    pub(crate) refresh_interval_waiting: u64,
+
+    /// process refresh interval in seconds while emerge is running
+    /// with inotify this limits how often activity triggers a rescan
+    pub(crate) refresh_interval_active: u64,
+
+    /// PORTAGE_TMPDIR, build dirs live in $PORTAGE_TMPDIR/portage
+    pub(crate) portage_tmpdir: PathBuf,
+
+    /// emerge log file, read for merge list progress
+    pub(crate) emerge_log: PathBuf,
+
+    /// also track `ebuild` commands run without emerge
+    pub(crate) ebuild_sessions: bool,
+
+    /// seconds a build failure stays shown, even after emerge exited
+    pub(crate) failure_grace_period: u64,
+
+    /// seconds the summary of a finished emerge stays shown
+    pub(crate) summary_duration: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Inotify,
+            refresh_interval_waiting: 5,
+            refresh_interval_active: 1,
+            portage_tmpdir: PathBuf::from("/var/tmp"),
+            emerge_log: PathBuf::from("/var/log/emerge.log"),
+            ebuild_sessions: false,
+            failure_grace_period: 30,
+            summary_duration: 15,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PresenceConfig {
+    /// asset key of the large image
+    pub(crate) large_image: String,
+
+    /// hover text of the large image
+    /// if unset the output of `ebuild --version` is used
+    pub(crate) large_text: Option<String>,
+
+    /// presence text
+    pub(crate) templates: TemplatesConfig,
+
+    /// asset key of the small image while dependencies are calculated
+    pub(crate) resolving_icon: String,
+
+    /// asset key of the small image while sources are downloaded
+    pub(crate) fetch_icon: String,
+
+    /// asset key of the small image while a binary package is merged
+    pub(crate) binary_icon: String,
+
+    /// asset key of the small image after a build failed
+    pub(crate) failed_icon: String,
+
+    /// asset key of the small image for the summary after emerge finished
+    pub(crate) summary_icon: String,
+
+    /// asset keys of the small image per phase
+    pub(crate) phase_icons: HashMap<String, String>,
+
+    /// asset key of the small image for phases without an icon
+    pub(crate) unknown_phase_icon: String,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        let phase_icons = Phase::KNOWN
+            .iter()
+            .map(|phase| (phase.to_string(), format!("phase_{}", phase)))
+            .collect();
+
+        Self {
+            large_image: String::from("gentoo_box"),
+            large_text: None,
+            templates: TemplatesConfig::default(),
+            resolving_icon: String::from("resolving"),
+            fetch_icon: String::from("fetch"),
+            binary_icon: String::from("binpkg"),
+            failed_icon: String::from("failed"),
+            summary_icon: String::from("summary"),
+            phase_icons,
+            unknown_phase_icon: String::from("phase_unknown"),
+        }
+    }
+}
+
+/// details is the first line of the presence, state the second
+/// placeholders are checked when the config is loaded
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TemplatesConfig {
+    /// first line while a single job runs
+    pub(crate) single_details: Template,
+
+    /// second line while a single job runs
+    pub(crate) single_state: Template,
+
+    /// first line while a single job runs with privacy mode "category"
+    pub(crate) category_details: Template,
+
+    /// first line while a single job runs with privacy mode "anonymous"
+    pub(crate) anonymous_details: Template,
+
+    /// first line while a single job of an `ebuild` command runs
+    pub(crate) ebuild_details: Template,
+
+    /// second line while a single binary package job runs
+    pub(crate) binary_state: Template,
+
+    /// second line while emerge merges the files of a single package
+    pub(crate) merge_state: Template,
+
+    /// second line while a single job compiles and its build log shows progress
+    pub(crate) compile_state: Template,
+
+    /// first line while multiple jobs run
+    pub(crate) multi_details: Template,
+
+    /// second line while multiple jobs run
+    pub(crate) multi_state: Template,
+
+    /// first line while emerge runs without jobs
+    pub(crate) idle_details: Template,
+
+    /// first line while emerge runs without jobs
+    /// but the merge list position is known
+    pub(crate) idle_progress_details: Template,
+
+    /// first line while emerge calculates dependencies before the first job
+    pub(crate) resolving_details: Template,
+
+    /// first line while no job runs after a build failed
+    pub(crate) failed_details: Template,
+
+    /// first line after a build of a hidden package failed
+    pub(crate) anonymous_failed_details: Template,
+
+    /// first line after emerge finished
+    pub(crate) summary_details: Template,
+
+    /// second line after emerge finished
+    pub(crate) summary_state: Template,
+
+    /// first line while sources are downloaded and no job runs
+    pub(crate) fetch_details: Template,
+
+    /// first line while sources of a hidden package are downloaded
+    pub(crate) anonymous_fetch_details: Template,
+
+    /// second line while sources are downloaded and no job runs
+    pub(crate) fetch_state: Template,
+
+    /// first line while emerge does something else than building
+    /// e.g. `--sync` or `--pretend`
+    pub(crate) mode_details: Template,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        // the defaults are known to be valid
+        let template = |source| Template::parse(source).unwrap();
+
+        Self {
+            single_details: template("{category}/{package}-{version}"),
+            single_state: template("Phase: {phase}"),
+            category_details: template("Building a {category} package"),
+            anonymous_details: template("Building a package"),
+            ebuild_details: template("Testing ebuild {ebuild}"),
+            binary_state: template("Installing binary package"),
+            merge_state: template("Merging into {root}"),
+            compile_state: template("Phase: {phase} — {build_progress}"),
+            multi_details: template("{job_count} Jobs Running"),
+            multi_state: template("Phases: {phases}"),
+            idle_details: template("No Jobs Running"),
+            idle_progress_details: template("Package {progress}"),
+            resolving_details: template("Calculating dependencies"),
+            failed_details: template("Build failed: {category}/{package} ({phase})"),
+            anonymous_failed_details: template("Build failed"),
+            summary_details: template("Emerge finished after {elapsed}"),
+            summary_state: template("{merged} merged, {failed} failed"),
+            fetch_details: template("Fetching {file} ({fetch_progress})"),
+            anonymous_fetch_details: template("Fetching sources ({fetch_progress})"),
+            fetch_state: template("{fetch_speed}"),
+            mode_details: template("{mode}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SinksConfig {
+    /// set the Discord rich presence
+    pub(crate) discord: bool,
+
+    /// write JSON status lines to a file
+    pub(crate) jsonl: Option<PathSinkConfig>,
+
+    /// broadcast JSON status lines on a unix socket
+    pub(crate) socket: Option<PathSinkConfig>,
+
+    /// send desktop notifications over D-Bus
+    pub(crate) notify: Option<NotifyConfig>,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            discord: true,
+            jsonl: None,
+            socket: None,
+            notify: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PathSinkConfig {
+    /// output file or socket, `-` means stdout where supported
+    pub(crate) path: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct NotifyConfig {
+    /// seconds a package has to build to get a notification when merged
+    pub(crate) long_build_threshold: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            long_build_threshold: 1800,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct PrivacyConfig {
+    /// how much of every job is shown
+    pub(crate) mode: Visibility,
+
+    /// packages that are always shown anonymously
+    pub(crate) redact: Vec<AtomPattern>,
+
+    /// packages that are not shown at all
+    pub(crate) drop: Vec<AtomPattern>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ApiConfig {
+    /// serve status requests
+    pub(crate) enabled: bool,
+
+    /// socket path
+    /// if unset $XDG_RUNTIME_DIR/portpresence.sock or /run/portpresence.sock is used
+    pub(crate) path: Option<PathBuf>,
+
+    /// socket permissions, only the owner by default
+    pub(crate) mode: u32,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+            mode: 0o600,
+        }
+    }
+}
+
+impl ApiConfig {
+    /// configured socket path or the default location
+    pub(crate) fn socket_path(&self) -> PathBuf {
+        if let Some(ref path) = self.path {
+            return path.clone();
+        }
+
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(API_SOCKET_NAME),
+            _ => Path::new("/run").join(API_SOCKET_NAME),
+        }
+    }
+}
+
+impl Config {
+    /// load the first config file found in the default locations
+    /// falls back to the built-in defaults if none exists
+    pub(crate) fn load() -> Result<Self, String> {
+        for path in default_paths() {
+            if path.exists() {
+                return Self::from_file(&path);
+            }
+        }
+
+        Ok(Self::default())
+    }
+
+    /// load and validate a config file
+    pub(crate) fn from_file(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| format!("Could not parse config {}: {}", path.display(), e))?;
+
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+
+        Ok(config)
+    }
+
+    /// check values that are syntactically fine but make no sense
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let client_id = &self.discord.client_id;
+        if client_id.is_empty() || !client_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!(
+                "discord.client_id must be a numeric application ID, got \"{}\"",
+                client_id
+            ));
+        }
+
+        if self.watcher.refresh_interval_waiting == 0 {
+            return Err(String::from(
+                "watcher.refresh_interval_waiting must be at least 1 second",
+            ));
+        }
+
+        if self.watcher.refresh_interval_active == 0 {
+            return Err(String::from(
+                "watcher.refresh_interval_active must be at least 1 second",
+            ));
+        }
+
+        if self.presence.large_image.is_empty() {
+            return Err(String::from("presence.large_image must not be empty"));
+        }
+
+        if self.presence.resolving_icon.is_empty() {
+            return Err(String::from("presence.resolving_icon must not be empty"));
+        }
+
+        if self.presence.fetch_icon.is_empty() {
+            return Err(String::from("presence.fetch_icon must not be empty"));
+        }
+
+        if self.presence.binary_icon.is_empty() {
+            return Err(String::from("presence.binary_icon must not be empty"));
+        }
+
+        if self.presence.failed_icon.is_empty() {
+            return Err(String::from("presence.failed_icon must not be empty"));
+        }
+
+        if self.presence.summary_icon.is_empty() {
+            return Err(String::from("presence.summary_icon must not be empty"));
+        }
+
+        if self.presence.unknown_phase_icon.is_empty() {
+            return Err(String::from(
+                "presence.unknown_phase_icon must not be empty",
+            ));
+        }
+
+        if self.api.mode > 0o777 {
+            return Err(format!(
+                "api.mode {:#o} is not a permission mode",
+                self.api.mode
+            ));
+        }
+
+        let sinks = &self.sinks;
+        if !sinks.discord
+            && sinks.jsonl.is_none()
+            && sinks.socket.is_none()
+            && sinks.notify.is_none()
+        {
+            return Err(String::from("at least one sink must be enabled"));
+        }
+
+        for (phase, icon) in &self.presence.phase_icons {
+            if let Phase::Unknown(_) = Phase::from_name(phase) {
+                return Err(format!(
+                    "presence.phase_icons.{} is not an ebuild phase",
+                    phase
+                ));
+            }
+            if icon.is_empty() {
+                return Err(format!("presence.phase_icons.{} must not be empty", phase));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// config file candidates in order of precedence
+fn default_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    // $XDG_CONFIG_HOME or ~/.config as per XDG base dir spec
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    };
+    if let Some(config_home) = config_home {
+        paths.push(config_home.join(USER_CONFIG_PATH));
+    }
+
+    paths.push(PathBuf::from(SYSTEM_CONFIG_PATH));
+    paths
+}
@@ -0,0 +1,579 @@
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream as StdUnixStream};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+// message types and header fields from the D-Bus specification
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// size of the fixed part of a message header
+/// up to and including the length of the header field array
+const FIXED_HEADER_LEN: usize = 16;
+
+/// largest message the specification allows
+const MAX_MESSAGE_LEN: usize = 128 * 1024 * 1024;
+
+/// just enough of a D-Bus client to call methods on the session bus
+/// zbus can't be used, psutil pins an older memchr than all its versions need
+pub(crate) struct SessionBus {
+    stream: BufReader<UnixStream>,
+
+    /// serial of the last message sent
+    serial: u32,
+}
+
+/// method to call
+pub(crate) struct MethodCall<'a> {
+    pub(crate) destination: &'a str,
+    pub(crate) path: &'a str,
+    pub(crate) interface: &'a str,
+    pub(crate) member: &'a str,
+
+    /// signature of the body like `susssasa{sv}i`
+    pub(crate) signature: &'a str,
+
+    /// arguments marshalled with `Writer`
+    pub(crate) body: Vec<u8>,
+}
+
+impl SessionBus {
+    /// connect to $DBUS_SESSION_BUS_ADDRESS or $XDG_RUNTIME_DIR/bus
+    pub(crate) async fn connect() -> Result<Self, String> {
+        let address = match env::var("DBUS_SESSION_BUS_ADDRESS") {
+            Ok(address) => address,
+            Err(_) => match env::var("XDG_RUNTIME_DIR") {
+                Ok(dir) => format!("unix:path={}/bus", dir),
+                Err(_) => return Err(String::from("No session bus address")),
+            },
+        };
+        Self::connect_to(&address).await
+    }
+
+    /// connect to a bus address like `unix:path=/run/user/1000/bus`
+    /// and authenticate as the current user
+    pub(crate) async fn connect_to(address: &str) -> Result<Self, String> {
+        let mut last_error = format!("No usable address in {}", address);
+        let mut stream = None;
+        // the first address that works wins
+        for socket in address.split(';').filter_map(parse_address) {
+            match connect_socket(&socket) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        let Some(stream) = stream else {
+            return Err(last_error);
+        };
+
+        let mut bus = Self {
+            stream: BufReader::new(stream),
+            serial: 0,
+        };
+        bus.authenticate().await?;
+
+        // the bus drops clients that don't say hello first
+        bus.call(MethodCall {
+            destination: "org.freedesktop.DBus",
+            path: "/org/freedesktop/DBus",
+            interface: "org.freedesktop.DBus",
+            member: "Hello",
+            signature: "",
+            body: Vec::new(),
+        })
+        .await?;
+
+        Ok(bus)
+    }
+
+    /// SASL EXTERNAL, the bus checks our uid on the socket
+    async fn authenticate(&mut self) -> Result<(), String> {
+        // SAFETY: getuid can't fail
+        let uid = unsafe { libc::getuid() };
+        let hex_uid: String = uid
+            .to_string()
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let auth = format!("\0AUTH EXTERNAL {}\r\n", hex_uid);
+        self.write(auth.as_bytes()).await?;
+
+        let mut reply = String::new();
+        self.stream
+            .read_line(&mut reply)
+            .await
+            .map_err(|e| e.to_string())?;
+        if !reply.starts_with("OK ") {
+            return Err(format!("Authentication rejected: {}", reply.trim()));
+        }
+
+        self.write(b"BEGIN\r\n").await
+    }
+
+    /// call a method and wait for its reply
+    /// returns the body of the reply
+    pub(crate) async fn call(&mut self, call: MethodCall<'_>) -> Result<Vec<u8>, String> {
+        self.serial += 1;
+        let serial = self.serial;
+        let message = encode_call(&call, serial);
+        self.write(&message).await?;
+
+        // skip signals and anything else until our reply
+        loop {
+            let reply = self.read_message().await?;
+            if reply.reply_serial != Some(serial) {
+                continue;
+            }
+            return match reply.kind {
+                METHOD_RETURN => Ok(reply.body),
+                ERROR => Err(format!(
+                    "{} failed: {}",
+                    call.member,
+                    reply.error_name.unwrap_or_default()
+                )),
+                _ => continue,
+            };
+        }
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> Result<(), String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(buf).await.map_err(|e| e.to_string())?;
+        stream.flush().await.map_err(|e| e.to_string())
+    }
+
+    /// read and parse one whole message
+    async fn read_message(&mut self) -> Result<Message, String> {
+        let (message, big_endian, header_len) = read_raw_message(&mut self.stream).await?;
+        Message::parse(&message, big_endian, header_len)
+            .ok_or_else(|| String::from("Malformed message"))
+    }
+}
+
+/// read one whole message
+/// returns it with its endianness and header length including padding
+async fn read_raw_message(
+    stream: &mut BufReader<UnixStream>,
+) -> Result<(Vec<u8>, bool, usize), String> {
+    let mut fixed = [0u8; FIXED_HEADER_LEN];
+    stream
+        .read_exact(&mut fixed)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let big_endian = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        other => return Err(format!("Invalid endianness marker {}", other)),
+    };
+    let body_len = Reader::u32_at(&fixed, 4, big_endian) as usize;
+    let fields_len = Reader::u32_at(&fixed, 12, big_endian) as usize;
+
+    // header fields are padded to 8 bytes before the body
+    let header_len = align(FIXED_HEADER_LEN + fields_len, 8);
+    let total = header_len + body_len;
+    if total > MAX_MESSAGE_LEN {
+        return Err(format!("Message too long ({} bytes)", total));
+    }
+
+    let mut message = vec![0u8; total];
+    message[..FIXED_HEADER_LEN].copy_from_slice(&fixed);
+    stream
+        .read_exact(&mut message[FIXED_HEADER_LEN..])
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok((message, big_endian, header_len))
+}
+
+/// where the bus listens
+#[derive(Debug, PartialEq)]
+enum BusSocket {
+    Path(String),
+    Abstract(String),
+}
+
+/// parse one address like `unix:path=/run/user/1000/bus,guid=...`
+/// only unix sockets are supported
+fn parse_address(address: &str) -> Option<BusSocket> {
+    let options = address.strip_prefix("unix:")?;
+    for option in options.split(',') {
+        match option.split_once('=') {
+            Some(("path", path)) => return Some(BusSocket::Path(unescape(path)?)),
+            Some(("abstract", name)) => return Some(BusSocket::Abstract(unescape(name)?)),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// undo the `%2f` escaping of address values
+fn unescape(value: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn connect_socket(socket: &BusSocket) -> Result<UnixStream, String> {
+    let stream = match socket {
+        BusSocket::Path(path) => StdUnixStream::connect(path)
+            .map_err(|e| format!("Could not connect to {}: {}", path, e))?,
+        BusSocket::Abstract(name) => {
+            let addr = SocketAddr::from_abstract_name(name).map_err(|e| e.to_string())?;
+            StdUnixStream::connect_addr(&addr)
+                .map_err(|e| format!("Could not connect to @{}: {}", name, e))?
+        }
+    };
+    stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+    UnixStream::from_std(stream).map_err(|e| e.to_string())
+}
+
+/// round `offset` up to a multiple of `alignment`
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// little endian marshalling of D-Bus values
+/// offsets are relative to the start of the buffer, which has to be
+/// 8 byte aligned in the final message like header and body are
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub(crate) buf: Vec<u8>,
+}
+
+impl Writer {
+    /// pad with zeros to a multiple of `alignment`
+    pub(crate) fn align(&mut self, alignment: usize) {
+        self.buf.resize(align(self.buf.len(), alignment), 0);
+    }
+
+    pub(crate) fn byte(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(crate) fn i32(&mut self, value: i32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// also used for object paths
+    pub(crate) fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    pub(crate) fn signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// array with elements aligned to `alignment`
+    /// `write` adds the elements
+    pub(crate) fn array(&mut self, alignment: usize, write: impl FnOnce(&mut Self)) {
+        self.u32(0);
+        let len_offset = self.buf.len() - 4;
+        // the length doesn't count the padding before the first element
+        self.align(alignment);
+        let start = self.buf.len();
+        write(self);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_offset..len_offset + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    /// start of a struct or dict entry
+    pub(crate) fn open_struct(&mut self) {
+        self.align(8);
+    }
+}
+
+/// build a method call message
+fn encode_call(call: &MethodCall, serial: u32) -> Vec<u8> {
+    let mut header = Writer::default();
+    header.byte(b'l');
+    header.byte(METHOD_CALL);
+    header.byte(0); // flags
+    header.byte(1); // protocol version
+    header.u32(call.body.len() as u32);
+    header.u32(serial);
+
+    let mut fields = vec![
+        (FIELD_PATH, "o", call.path),
+        (FIELD_INTERFACE, "s", call.interface),
+        (FIELD_MEMBER, "s", call.member),
+        (FIELD_DESTINATION, "s", call.destination),
+    ];
+    if !call.signature.is_empty() {
+        fields.push((FIELD_SIGNATURE, "g", call.signature));
+    }
+    header.array(8, |w| {
+        for (code, kind, value) in fields {
+            w.open_struct();
+            w.byte(code);
+            w.signature(kind);
+            match kind {
+                "g" => w.signature(value),
+                _ => w.string(value),
+            }
+        }
+    });
+    header.align(8);
+
+    header.buf.extend_from_slice(&call.body);
+    header.buf
+}
+
+/// the parts of a received message we need
+struct Message {
+    kind: u8,
+    reply_serial: Option<u32>,
+    error_name: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Message {
+    /// parse a whole message, the header is `header_len` bytes with padding
+    fn parse(buf: &[u8], big_endian: bool, header_len: usize) -> Option<Self> {
+        let mut message = Self {
+            kind: *buf.get(1)?,
+            reply_serial: None,
+            error_name: None,
+            body: buf.get(header_len..)?.to_vec(),
+        };
+
+        let fields_len = Reader::u32_at(buf, 12, big_endian) as usize;
+        let mut reader = Reader {
+            buf: buf.get(..FIXED_HEADER_LEN + fields_len)?,
+            offset: FIXED_HEADER_LEN,
+            big_endian,
+        };
+        while reader.offset < reader.buf.len() {
+            reader.align(8);
+            let code = reader.byte()?;
+            let signature = reader.signature()?;
+            match (code, signature.as_str()) {
+                (FIELD_REPLY_SERIAL, "u") => message.reply_serial = Some(reader.u32()?),
+                (FIELD_ERROR_NAME, "s") => message.error_name = Some(reader.string()?),
+                (_, "s" | "o") => drop(reader.string()?),
+                (_, "g") => drop(reader.signature()?),
+                (_, "u") => drop(reader.u32()?),
+                _ => return None, // no other types in header fields
+            }
+        }
+        Some(message)
+    }
+}
+
+/// unmarshalling of the few types used in header fields
+struct Reader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u32_at(buf: &[u8], offset: usize, big_endian: bool) -> u32 {
+        let bytes: [u8; 4] = buf[offset..offset + 4].try_into().unwrap();
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.offset = align(self.offset, alignment);
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.buf.get(self.offset)?;
+        self.offset += 1;
+        Some(b)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        self.buf.get(self.offset..self.offset + 4)?;
+        let value = Self::u32_at(self.buf, self.offset, self.big_endian);
+        self.offset += 4;
+        Some(value)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<String> {
+        let bytes = self.buf.get(self.offset..self.offset + len)?;
+        // skip the trailing nul
+        self.offset += len + 1;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = self.byte()? as usize;
+        self.bytes(len)
+    }
+}
+
+/// stand-in for the session bus in tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use tokio::net::UnixListener;
+
+    /// accept one client, authenticate it and answer `calls` method calls
+    /// the first is Hello, the others get a u32 like a notification id
+    /// returns the messages received
+    pub(crate) async fn serve(listener: UnixListener, calls: u32) -> Vec<Vec<u8>> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        assert!(line.starts_with("\0AUTH EXTERNAL "), "{:?}", line);
+        stream
+            .get_mut()
+            .write_all(b"OK 0123456789abcdef0123456789abcdef\r\n")
+            .await
+            .unwrap();
+        line.clear();
+        stream.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "BEGIN\r\n");
+
+        let mut received = Vec::new();
+        for serial in 1..=calls {
+            let (message, big_endian, _) = read_raw_message(&mut stream).await.unwrap();
+            let reply_serial = Reader::u32_at(&message, 8, big_endian);
+
+            let mut body = Writer::default();
+            let signature = match serial {
+                1 => {
+                    body.string(":1.42"); // unique name
+                    "s"
+                }
+                _ => {
+                    body.u32(serial);
+                    "u"
+                }
+            };
+            let mut reply = Writer::default();
+            reply.byte(b'l');
+            reply.byte(METHOD_RETURN);
+            reply.byte(0); // flags
+            reply.byte(1); // protocol version
+            reply.u32(body.buf.len() as u32);
+            reply.u32(serial);
+            reply.array(8, |w| {
+                w.open_struct();
+                w.byte(FIELD_REPLY_SERIAL);
+                w.signature("u");
+                w.u32(reply_serial);
+                w.open_struct();
+                w.byte(FIELD_SIGNATURE);
+                w.signature("g");
+                w.signature(signature);
+            });
+            reply.align(8);
+            reply.buf.extend_from_slice(&body.buf);
+            stream.get_mut().write_all(&reply.buf).await.unwrap();
+
+            received.push(message);
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            parse_address("unix:path=/run/user/1000/bus"),
+            Some(BusSocket::Path(String::from("/run/user/1000/bus")))
+        );
+        assert_eq!(
+            parse_address("unix:abstract=/tmp/dbus-XyZ%2c1,guid=4f2a"),
+            Some(BusSocket::Abstract(String::from("/tmp/dbus-XyZ,1")))
+        );
+        assert_eq!(parse_address("tcp:host=localhost,port=4711"), None);
+        assert_eq!(parse_address("unix:tmpdir=/tmp"), None);
+    }
+
+    #[test]
+    fn marshalling() {
+        let mut w = Writer::default();
+        w.byte(1);
+        w.string("abcde");
+        w.array(8, |w| {
+            w.open_struct();
+            w.string("k");
+            w.signature("y");
+            w.byte(2);
+        });
+        assert_eq!(
+            w.buf,
+            [
+                1, 0, 0, 0, // byte and padding
+                5, 0, 0, 0, b'a', b'b', b'c', b'd', b'e', 0, // string
+                0, 0, // padding
+                10, 0, 0, 0, // array length without the padding after it
+                0, 0, 0, 0, // padding to the first dict entry
+                1, 0, 0, 0, b'k', 0, // key
+                1, b'y', 0, // variant signature
+                2, // value
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let call = MethodCall {
+            destination: "org.freedesktop.DBus",
+            path: "/org/freedesktop/DBus",
+            interface: "org.freedesktop.DBus",
+            member: "Hello",
+            signature: "",
+            body: Vec::new(),
+        };
+        let message = encode_call(&call, 7);
+        assert_eq!(message.len() % 8, 0);
+        assert_eq!(Reader::u32_at(&message, 8, false), 7);
+
+        let parsed = Message::parse(&message, false, message.len()).unwrap();
+        assert_eq!(parsed.kind, METHOD_CALL);
+        assert_eq!(parsed.reply_serial, None);
+        assert!(parsed.body.is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::atom::Atom;
+use crate::session::MergeProgress;
+
+/// interesting lines from emerge.log
+#[derive(Debug)]
+pub(crate) enum LogEvent {
+    /// `>>> emerge (12 of 87) cat/pkg-ver to /`
+    Started {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+        progress: MergeProgress,
+
+        /// ROOT the package is merged into
+        root: String,
+    },
+
+    /// `=== (12 of 87) Merging (cat/pkg-ver::/path/to/pkg-ver.ebuild)`
+    /// or `Merging Binary` when installing a binary package
+    Merging {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+        binary: bool,
+    },
+
+    /// `=== (12 of 87) Post-Build Cleaning (cat/pkg-ver::/path/to/pkg-ver.ebuild)`
+    Cleaning {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+    },
+
+    /// `::: completed emerge (12 of 87) cat/pkg-ver to /`
+    Completed {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+    },
+
+    /// `=== Unmerging... (cat/pkg-ver)`, a replaced version or `--depclean`
+    Unmerging {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+    },
+
+    /// `>>> unmerge success: cat/pkg-ver`
+    Unmerged {
+        /// log timestamp in unix epoch duration
+        time: Duration,
+        atom: Atom,
+    },
+}
+
+impl LogEvent {
+    /// log timestamp in unix epoch duration
+    pub(crate) fn time(&self) -> Duration {
+        match self {
+            LogEvent::Started { time, .. }
+            | LogEvent::Merging { time, .. }
+            | LogEvent::Cleaning { time, .. }
+            | LogEvent::Completed { time, .. }
+            | LogEvent::Unmerging { time, .. }
+            | LogEvent::Unmerged { time, .. } => *time,
+        }
+    }
+
+    /// package the line is about
+    pub(crate) fn atom(&self) -> &Atom {
+        match self {
+            LogEvent::Started { atom, .. }
+            | LogEvent::Merging { atom, .. }
+            | LogEvent::Cleaning { atom, .. }
+            | LogEvent::Completed { atom, .. }
+            | LogEvent::Unmerging { atom, .. }
+            | LogEvent::Unmerged { atom, .. } => atom,
+        }
+    }
+}
+
+/// follows /var/log/emerge.log like `tail -f`
+pub(crate) struct EmergeLogTailer {
+    /// log file
+    path: PathBuf,
+
+    /// read position, None until the file was opened once
+    offset: Option<u64>,
+}
+
+impl EmergeLogTailer {
+    /// create a tailer, nothing is read until `read_new`
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset: None,
+        }
+    }
+
+    /// read lines appended since the last call
+    /// the first call reads the whole existing log for build history
+    pub(crate) fn read_new(&mut self) -> Vec<LogEvent> {
+        let mut events = Vec::new();
+
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return events, // no log (yet)
+        };
+        let len = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return events,
+        };
+
+        // start over on first read or if the log was rotated or truncated
+        let start = match self.offset {
+            Some(offset) if offset <= len => offset,
+            _ => 0,
+        };
+        if start == len {
+            self.offset = Some(len);
+            return events;
+        }
+        if file.seek(SeekFrom::Start(start)).is_err() {
+            return events;
+        }
+
+        let mut reader = BufReader::new(file);
+        let mut offset = start;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            // emerge is still writing this line, retry next time
+            if !line.ends_with(b"\n") {
+                break;
+            }
+            offset += n as u64;
+
+            if let Some(event) = parse_line(&String::from_utf8_lossy(&line)) {
+                events.push(event);
+            }
+        }
+
+        self.offset = Some(offset);
+        events
+    }
+}
+
+/// parse a single emerge.log line like
+/// `1718000010:  >>> emerge (1 of 87) dev-lang/python-3.12.4 to /`
+pub(crate) fn parse_line(line: &str) -> Option<LogEvent> {
+    let (time, message) = line.split_once(':')?;
+    let time = Duration::from_secs(time.trim().parse().ok()?);
+    let message = message.trim();
+
+    if let Some(rest) = message.strip_prefix(">>> emerge (") {
+        let (progress, rest) = parse_progress(rest)?;
+        let mut words = rest.split_ascii_whitespace();
+        let cpv = words.next()?;
+        // `cat/pkg-ver to /`
+        let root = match (words.next(), words.next()) {
+            (Some("to"), Some(root)) => String::from(root),
+            _ => String::from("/"),
+        };
+        return Some(LogEvent::Started {
+            time,
+            atom: cpv.parse().ok()?,
+            progress,
+            root,
+        });
+    }
+
+    if let Some(rest) = message.strip_prefix("=== (") {
+        let (_, rest) = parse_progress(rest)?;
+        let (step, rest) = rest.trim_start().split_once(" (")?;
+        // `cat/pkg-ver::/path` or `cat/pkg-ver::repo` depending on the version
+        let cpv = rest.split("::").next()?.trim_end_matches(')');
+        let atom = cpv.parse().ok()?;
+        return match step {
+            "Merging" => Some(LogEvent::Merging {
+                time,
+                atom,
+                binary: false,
+            }),
+            "Merging Binary" => Some(LogEvent::Merging {
+                time,
+                atom,
+                binary: true,
+            }),
+            "Post-Build Cleaning" => Some(LogEvent::Cleaning { time, atom }),
+            _ => None,
+        };
+    }
+
+    if let Some(rest) = message.strip_prefix("::: completed emerge (") {
+        let (_, rest) = parse_progress(rest)?;
+        let cpv = rest.split_ascii_whitespace().next()?;
+        return Some(LogEvent::Completed {
+            time,
+            atom: cpv.parse().ok()?,
+        });
+    }
+
+    if let Some(rest) = message.strip_prefix("=== Unmerging... (") {
+        let cpv = rest.strip_suffix(')')?;
+        return Some(LogEvent::Unmerging {
+            time,
+            atom: cpv.parse().ok()?,
+        });
+    }
+
+    if let Some(cpv) = message.strip_prefix(">>> unmerge success: ") {
+        return Some(LogEvent::Unmerged {
+            time,
+            atom: cpv.trim().parse().ok()?,
+        });
+    }
+
+    None
+}
+
+/// parse `12 of 87) rest` into progress and rest
+fn parse_progress(s: &str) -> Option<(MergeProgress, &str)> {
+    let (progress, rest) = s.split_once(')')?;
+    let (current, total) = progress.split_once(" of ")?;
+    let progress = MergeProgress {
+        current: current.trim().parse().ok()?,
+        total: total.trim().parse().ok()?,
+    };
+    Some((progress, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(cpv: &str) -> Atom {
+        cpv.parse().unwrap()
+    }
+
+    #[test]
+    fn started() {
+        let line = "1718000010:  >>> emerge (1 of 87) dev-lang/python-3.12.4 to /mnt/gentoo/";
+        match parse_line(line) {
+            Some(LogEvent::Started {
+                time,
+                atom: a,
+                progress,
+                root,
+            }) => {
+                assert_eq!(time, Duration::from_secs(1718000010));
+                assert_eq!(a, atom("dev-lang/python-3.12.4"));
+                assert_eq!(
+                    progress,
+                    MergeProgress {
+                        current: 1,
+                        total: 87
+                    }
+                );
+                assert_eq!(root, "/mnt/gentoo/");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn merge_steps() {
+        let line = "1718000300:  === (1 of 87) Merging (dev-lang/python-3.12.4::/var/db/repos/gentoo/dev-lang/python/python-3.12.4.ebuild)";
+        assert!(matches!(
+            parse_line(line),
+            Some(LogEvent::Merging { binary: false, atom: a, .. }) if a == atom("dev-lang/python-3.12.4")
+        ));
+
+        let line = "1718000300:  === (2 of 87) Merging Binary (app-misc/foo-1.0::/var/cache/binpkgs/app-misc/foo/foo-1.0-1.gpkg.tar)";
+        assert!(matches!(
+            parse_line(line),
+            Some(LogEvent::Merging { binary: true, .. })
+        ));
+
+        let line = "1718000310:  === (1 of 87) Post-Build Cleaning (dev-lang/python-3.12.4::/var/db/repos/gentoo/dev-lang/python/python-3.12.4.ebuild)";
+        assert!(matches!(parse_line(line), Some(LogEvent::Cleaning { .. })));
+    }
+
+    #[test]
+    fn other_steps() {
+        for line in [
+            "1718000010:  === (1 of 87) Cleaning (dev-lang/python-3.12.4::/var/db/repos/gentoo/dev-lang/python/python-3.12.4.ebuild)",
+            "1718000010:  === (1 of 87) Compiling/Merging (dev-lang/python-3.12.4::/var/db/repos/gentoo/dev-lang/python/python-3.12.4.ebuild)",
+            "1718000320:  >>> AUTOCLEAN: dev-lang/python:3.12",
+            "1718000321:  >>> unmerge: dev-lang/python-3.12.3",
+            // emerge.log has no failure details, those are in the build log
+            "1718000330:  *** exiting unsuccessfully with status '1'.",
+        ] {
+            assert!(parse_line(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn unmerge_steps() {
+        let line = "1718000320:  === Unmerging... (dev-lang/python-3.12.3)";
+        assert!(matches!(
+            parse_line(line),
+            Some(LogEvent::Unmerging { atom: a, .. }) if a == atom("dev-lang/python-3.12.3")
+        ));
+
+        let line = "1718000325:  >>> unmerge success: dev-lang/python-3.12.3";
+        assert!(matches!(
+            parse_line(line),
+            Some(LogEvent::Unmerged { atom: a, .. }) if a == atom("dev-lang/python-3.12.3")
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Serialize;
+
+/// what an emerge process was asked to do
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EmergeMode {
+    /// merge packages, the default action
+    #[default]
+    Build,
+
+    /// `--pretend`, only resolves dependencies
+    Pretend,
+
+    /// `--fetchonly` or `--fetch-all-uri`
+    Fetch,
+
+    /// `--depclean`, `--unmerge`, `--prune` or `--rage-clean`
+    Remove,
+
+    /// `--sync`
+    Sync,
+
+    /// `--search` or `--searchdesc`
+    Search,
+
+    /// `ebuild(1)` run directly instead of emerge
+    Ebuild,
+
+    /// anything else that doesn't build, like `--regen`, `--info` or `--config`
+    Other,
+}
+
+impl EmergeMode {
+    /// parse the emerge arguments, without the interpreter and emerge itself
+    pub(crate) fn from_args<'a>(args: impl IntoIterator<Item = &'a str>) -> Self {
+        let (mut pretend, mut fetch, mut remove, mut sync, mut search, mut other) =
+            (false, false, false, false, false, false);
+
+        for arg in args {
+            if let Some(long) = arg.strip_prefix("--") {
+                // --opt=value
+                let name = long.split_once('=').map_or(long, |(name, _)| name);
+                match name {
+                    "pretend" => pretend = true,
+                    "fetchonly" | "fetch-all-uri" => fetch = true,
+                    "depclean" | "unmerge" | "prune" | "rage-clean" => remove = true,
+                    "sync" => sync = true,
+                    "search" | "searchdesc" => search = true,
+                    "regen" | "metadata" | "info" | "config" | "check-news" | "list-sets"
+                    | "help" | "version" => other = true,
+                    _ => (),
+                }
+            } else if let Some(short) = arg.strip_prefix('-') {
+                // clustered short options like -avuDN or -pv
+                for c in short.chars() {
+                    match c {
+                        'p' => pretend = true,
+                        'f' | 'F' => fetch = true,
+                        'c' | 'C' | 'P' => remove = true,
+                        's' | 'S' => search = true,
+                        'h' | 'V' => other = true,
+                        _ => (),
+                    }
+                }
+            }
+        }
+
+        // actions that ignore --pretend first
+        // then pretend wins since nothing is fetched or removed
+        if sync {
+            EmergeMode::Sync
+        } else if search {
+            EmergeMode::Search
+        } else if other {
+            EmergeMode::Other
+        } else if pretend {
+            EmergeMode::Pretend
+        } else if remove {
+            EmergeMode::Remove
+        } else if fetch {
+            EmergeMode::Fetch
+        } else {
+            EmergeMode::Build
+        }
+    }
+
+    /// human readable activity
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            EmergeMode::Build => "Building packages",
+            EmergeMode::Pretend => "Resolving dependencies",
+            EmergeMode::Fetch => "Fetching sources",
+            EmergeMode::Remove => "Removing packages",
+            EmergeMode::Sync => "Syncing repositories",
+            EmergeMode::Search => "Searching packages",
+            EmergeMode::Ebuild => "Testing ebuild",
+            EmergeMode::Other => "Running emerge",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(args: &str) -> EmergeMode {
+        EmergeMode::from_args(args.split_ascii_whitespace())
+    }
+
+    #[test]
+    fn build() {
+        assert_eq!(mode("-avuDN @world"), EmergeMode::Build);
+        assert_eq!(
+            mode("--ask --verbose --jobs=4 dev-lang/python"),
+            EmergeMode::Build
+        );
+        assert_eq!(mode("-1 -j4 sys-kernel/gentoo-sources"), EmergeMode::Build);
+    }
+
+    #[test]
+    fn short_options() {
+        assert_eq!(mode("-pv @world"), EmergeMode::Pretend);
+        assert_eq!(mode("-fq @world"), EmergeMode::Fetch);
+        assert_eq!(mode("-ac"), EmergeMode::Remove);
+        assert_eq!(mode("-C app-misc/foo"), EmergeMode::Remove);
+        assert_eq!(mode("-s python"), EmergeMode::Search);
+    }
+
+    #[test]
+    fn long_options() {
+        assert_eq!(mode("--sync"), EmergeMode::Sync);
+        assert_eq!(mode("--depclean --ask"), EmergeMode::Remove);
+        assert_eq!(mode("--fetchonly --deep @world"), EmergeMode::Fetch);
+        assert_eq!(mode("--searchdesc portage"), EmergeMode::Search);
+    }
+
+    #[test]
+    fn other_actions() {
+        for args in [
+            "--regen",
+            "--metadata",
+            "--info",
+            "--config dev-db/postgresql",
+            "--check-news",
+            "--list-sets",
+            "--regen --jobs=8",
+            "--info -p",
+            "-V",
+        ] {
+            assert_eq!(mode(args), EmergeMode::Other, "{}", args);
+        }
+        // not confused with --verbose
+        assert_eq!(mode("-v @world"), EmergeMode::Build);
+    }
+
+    #[test]
+    fn pretend_wins() {
+        assert_eq!(mode("-pc"), EmergeMode::Pretend);
+        assert_eq!(mode("--pretend --fetchonly @world"), EmergeMode::Pretend);
+    }
+
+    #[test]
+    fn packages_are_not_options() {
+        assert_eq!(mode("app-misc/scpc"), EmergeMode::Build);
+        assert_eq!(mode("--usepkg=y sys-apps/portage"), EmergeMode::Build);
+    }
+}
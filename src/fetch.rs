@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::session::unix_now;
+
+/// fetchers portage calls through FETCHCOMMAND and RESUMECOMMAND
+const FETCHERS: [&str; 3] = ["wget", "curl", "aria2c"];
+
+/// shortest time to measure download speed over
+const MIN_SPEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// suffix portage adds to distfiles while they are downloaded
+const DOWNLOAD_SUFFIX: &str = ".__download__";
+
+/// a distfile being downloaded
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FetchJob {
+    /// distfile name
+    pub(crate) file: String,
+
+    /// file the fetcher writes to
+    pub(crate) path: PathBuf,
+
+    /// ebuild category, if the fetcher environment was readable
+    pub(crate) category: Option<String>,
+
+    /// ebuild package, if the fetcher environment was readable
+    pub(crate) package: Option<String>,
+
+    /// ebuild repository, if the fetcher environment was readable
+    pub(crate) repository: Option<String>,
+
+    /// bytes downloaded so far
+    pub(crate) size: u64,
+
+    /// distfile size from the Manifest
+    pub(crate) total: Option<u64>,
+
+    /// bytes per second since the last sample
+    pub(crate) speed: Option<u64>,
+
+    /// fetcher process creation time in unix epoch duration
+    pub(crate) create_time: Duration,
+
+    /// start of the current speed measurement in unix epoch duration
+    sampled: Duration,
+
+    /// size at the start of the current speed measurement
+    sampled_size: u64,
+}
+
+impl FetchJob {
+    /// describe a fetcher process from its cmdline and environment
+    /// None if it's not a fetcher or not writing to a file
+    pub(crate) fn from_proc(
+        cmdline: &[String],
+        environ: &HashMap<String, String>,
+        create_time: Duration,
+    ) -> Option<Self> {
+        if !is_fetcher(cmdline) {
+            return None;
+        }
+        let exe = Path::new(&cmdline[0]).file_name()?.to_str()?;
+
+        let path = output_path(exe, &cmdline[1..])?;
+        let name = path.file_name()?.to_str()?;
+        let file = String::from(name.strip_suffix(DOWNLOAD_SUFFIX).unwrap_or(name));
+
+        // portage sets up the ebuild environment for fetching
+        // O is the package dir in the repository
+        let total = environ
+            .get("O")
+            .and_then(|dir| manifest_size(&Path::new(dir).join("Manifest"), &file));
+
+        let mut job = Self {
+            file,
+            path,
+            category: environ.get("CATEGORY").cloned(),
+            package: environ.get("PN").cloned(),
+            repository: environ.get("PORTAGE_REPO_NAME").cloned(),
+            size: 0,
+            total,
+            speed: None,
+            create_time,
+            sampled: create_time,
+            sampled_size: 0,
+        };
+        job.sample();
+        Some(job)
+    }
+
+    /// read the current size of the download
+    /// returns whether anything changed
+    pub(crate) fn sample(&mut self) -> bool {
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return false;
+        };
+        let size = metadata.len();
+        let changed = size != self.size;
+        self.size = size;
+
+        // measure over at least a second, samples can be close together
+        let now = unix_now();
+        let elapsed = now.saturating_sub(self.sampled);
+        if elapsed < MIN_SPEED_INTERVAL {
+            return changed;
+        }
+
+        let speed = (size.saturating_sub(self.sampled_size) as f64 / elapsed.as_secs_f64()) as u64;
+        self.sampled = now;
+        self.sampled_size = size;
+        let changed = changed || self.speed != Some(speed);
+        self.speed = Some(speed);
+        changed
+    }
+
+    /// `45%` if the size is known, otherwise the downloaded size
+    pub(crate) fn progress(&self) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!("{}%", (self.size * 100 / total).min(100)),
+            _ => format_size(self.size),
+        }
+    }
+}
+
+/// whether the cmdline runs one of the known fetchers
+pub(crate) fn is_fetcher(cmdline: &[String]) -> bool {
+    cmdline
+        .first()
+        .and_then(|exe| Path::new(exe).file_name())
+        .and_then(|exe| exe.to_str())
+        .is_some_and(|exe| FETCHERS.contains(&exe))
+}
+
+/// file a fetcher writes to, from its arguments
+fn output_path(exe: &str, args: &[String]) -> Option<PathBuf> {
+    let (short, long) = match exe {
+        "wget" => ("-O", "--output-document"),
+        "curl" => ("-o", "--output"),
+        // aria2c splits the path into -d dir and -o name
+        _ => {
+            let dir = option_value(args, "-d", "--dir")?;
+            let name = option_value(args, "-o", "--out")?;
+            return Some(Path::new(&dir).join(name));
+        }
+    };
+    option_value(args, short, long).map(PathBuf::from)
+}
+
+/// value of `-o value`, `-ovalue`, `--long value` or `--long=value`
+fn option_value(args: &[String], short: &str, long: &str) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == short || arg == long {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg
+            .strip_prefix(long)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(String::from(value));
+        }
+        if let Some(value) = arg.strip_prefix(short)
+            && !value.is_empty()
+            && !arg.starts_with("--")
+        {
+            return Some(String::from(value));
+        }
+    }
+    None
+}
+
+/// size of a distfile from a line like
+/// `DIST firefox-128.0.source.tar.xz 543210 BLAKE2B ... SHA512 ...`
+fn manifest_size(manifest: &Path, file: &str) -> Option<u64> {
+    let content = fs::read_to_string(manifest).ok()?;
+    content.lines().find_map(|line| {
+        let mut fields = line.split_ascii_whitespace();
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some("DIST"), Some(name), Some(size)) if name == file => size.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// human readable size like `12.3 MiB`
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_ascii_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn default_fetchcommands() {
+        let wget = args(
+            "wget -t 3 -T 60 --passive-ftp -O /var/cache/distfiles/foo-1.0.tar.xz.__download__ https://example.org/foo-1.0.tar.xz",
+        );
+        assert!(is_fetcher(&wget));
+        assert_eq!(
+            output_path("wget", &wget[1..]),
+            Some(PathBuf::from(
+                "/var/cache/distfiles/foo-1.0.tar.xz.__download__"
+            ))
+        );
+
+        let curl = args("/usr/bin/curl --retry 3 -f -L --output /d/foo.zip https://x/foo.zip");
+        assert!(is_fetcher(&curl));
+        assert_eq!(
+            output_path("curl", &curl[1..]),
+            Some(PathBuf::from("/d/foo.zip"))
+        );
+
+        let aria2c = args("aria2c -d /d -o foo.tar.gz --allow-overwrite=true https://x/foo.tar.gz");
+        assert_eq!(
+            output_path("aria2c", &aria2c[1..]),
+            Some(PathBuf::from("/d/foo.tar.gz"))
+        );
+    }
+
+    #[test]
+    fn attached_values() {
+        assert_eq!(
+            option_value(&args("-q -O/d/foo"), "-O", "--output-document"),
+            Some(String::from("/d/foo"))
+        );
+        assert_eq!(
+            option_value(&args("--output-document=/d/foo"), "-O", "--output-document"),
+            Some(String::from("/d/foo"))
+        );
+        assert_eq!(
+            option_value(&args("-q https://x/foo"), "-O", "--output-document"),
+            None
+        );
+        assert!(!is_fetcher(&args("rsync -a x y")));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(45 * 1024 * 1024), "45.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,192 @@
+use futures::StreamExt;
+use inotify::{EventMask, EventOwned, EventStream, Inotify, WatchDescriptor, WatchMask, Watches};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::time::{Duration, Instant, timeout, timeout_at};
+
+/// levels of $PORTAGE_TMPDIR/portage we care about
+/// e.g. /var/tmp/portage/<cat>/<pf>/.compiled
+/// build logs are sampled on a timer, so temp/ isn't watched
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    /// $PORTAGE_TMPDIR, only watched until portage/ shows up
+    /// e.g. on a fresh tmpfs after boot
+    Tmpdir,
+
+    /// $PORTAGE_TMPDIR/portage, category dirs get created here
+    Root,
+
+    /// <cat>, build dirs get created here
+    Category,
+
+    /// <cat>/<pf>, .unpacked, .compiled etc. markers get created here
+    BuildDir,
+}
+
+impl Level {
+    /// level of subdirectories and whether a subdir named `name` is relevant
+    fn child(self, name: &str) -> Option<Level> {
+        match self {
+            Level::Tmpdir if name == "portage" => Some(Level::Root),
+            Level::Root => Some(Level::Category),
+            Level::Category => Some(Level::BuildDir),
+            _ => None,
+        }
+    }
+
+    /// events to subscribe to on this level
+    fn mask(self) -> WatchMask {
+        match self {
+            Level::Tmpdir | Level::Root | Level::Category => {
+                WatchMask::CREATE | WatchMask::MOVED_TO
+            }
+            Level::BuildDir => WatchMask::CREATE | WatchMask::DELETE | WatchMask::MOVED_TO,
+        }
+    }
+}
+
+/// wakes the process watcher on activity in portage build dirs
+/// so we don't have to poll /proc while nothing happens
+pub(crate) struct BuildDirWatcher {
+    /// inotify event source
+    stream: EventStream<[u8; 4096]>,
+
+    /// handle for adding watches to `stream`
+    watches: Watches,
+
+    /// watched directories
+    dirs: HashMap<WatchDescriptor, (PathBuf, Level)>,
+}
+
+impl BuildDirWatcher {
+    /// start watching `$portage_tmpdir/portage` and `emerge_log`
+    pub(crate) fn new(portage_tmpdir: &Path, emerge_log: &Path) -> Result<Self, String> {
+        let inotify = Inotify::init().map_err(|e| e.to_string())?;
+        let stream = inotify
+            .into_event_stream([0; 4096])
+            .map_err(|e| e.to_string())?;
+
+        let mut watcher = Self {
+            watches: stream.watches(),
+            stream,
+            dirs: HashMap::new(),
+        };
+
+        watcher
+            .add_root(portage_tmpdir)
+            .map_err(|e| format!("Could not watch {}: {}", portage_tmpdir.display(), e))?;
+
+        // emerge logs session start and end here
+        // which catches emerge processes without any jobs
+        if let Err(e) = watcher.watches.add(emerge_log, WatchMask::MODIFY) {
+            eprintln!("Could not watch {}: {}", emerge_log.display(), e);
+        }
+
+        Ok(watcher)
+    }
+
+    /// watch $PORTAGE_TMPDIR/portage or $PORTAGE_TMPDIR until it's created
+    fn add_root(&mut self, portage_tmpdir: &Path) -> Result<(), String> {
+        match self.add_dir(&portage_tmpdir.join("portage"), Level::Root) {
+            Ok(()) => Ok(()),
+            Err(_) => self.add_dir(portage_tmpdir, Level::Tmpdir),
+        }
+    }
+
+    /// watch a directory and all relevant existing subdirectories
+    fn add_dir(&mut self, path: &Path, level: Level) -> Result<(), String> {
+        let wd = self
+            .watches
+            .add(path, level.mask() | WatchMask::ONLYDIR)
+            .map_err(|e| e.to_string())?;
+        self.dirs.insert(wd, (path.to_path_buf(), level));
+
+        // anything created before the watch was added needs to be picked up manually
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()), // removed again already
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(child) = level.child(&name.to_string_lossy()) else {
+                continue;
+            };
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                // build dirs come and go, failing on one of them is fine
+                let _ = self.add_dir(&entry.path(), child);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// wait until something happens in the build dirs or `max_wait` elapses
+    /// after the first event we keep collecting events for `settle`
+    /// so a burst of activity only causes a single rescan
+    /// returns whether processes need a rescan, false if it timed out
+    pub(crate) async fn wait(
+        &mut self,
+        max_wait: Duration,
+        settle: Duration,
+    ) -> Result<bool, String> {
+        let mut activity = match timeout(max_wait, self.stream.next()).await {
+            Ok(Some(event)) => self.handle(event.map_err(|e| e.to_string())?),
+            Ok(None) => return Err(String::from("inotify stream ended")),
+            Err(_) => return Ok(false), // timed out
+        };
+
+        let deadline = Instant::now() + settle;
+        while let Ok(event) = timeout_at(deadline, self.stream.next()).await {
+            match event {
+                Some(event) => activity |= self.handle(event.map_err(|e| e.to_string())?),
+                None => return Err(String::from("inotify stream ended")),
+            }
+        }
+
+        Ok(activity)
+    }
+
+    /// keep the watched dirs in sync with the event
+    /// returns whether it's a build dir, marker or emerge.log change
+    fn handle(&mut self, event: EventOwned) -> bool {
+        #[cfg(debug_assertions)]
+        println!("Build dir event: {:?} {:?}", event.mask, event.name);
+
+        // watch removed by the kernel because the dir is gone
+        if event.mask.contains(EventMask::IGNORED) {
+            // wait for portage/ to be created again
+            if let Some((path, Level::Root)) = self.dirs.remove(&event.wd)
+                && let Some(portage_tmpdir) = path.parent()
+            {
+                let _ = self.add_dir(portage_tmpdir, Level::Tmpdir);
+            }
+            return false;
+        }
+
+        let Some((parent, level)) = self.dirs.get(&event.wd).cloned() else {
+            return true; // emerge.log
+        };
+        let child = event
+            .name
+            .as_ref()
+            .and_then(|name| level.child(&name.to_string_lossy()));
+
+        // descend into new directories
+        if event.mask.contains(EventMask::ISDIR)
+            && let Some(name) = &event.name
+            && let Some(child) = child
+        {
+            let _ = self.add_dir(&parent.join(name), child);
+
+            // portage/ replaces the watch on $PORTAGE_TMPDIR
+            if level == Level::Tmpdir {
+                self.dirs.remove(&event.wd);
+                let _ = self.watches.remove(event.wd);
+            }
+        }
+
+        // anything else created in $PORTAGE_TMPDIR is none of our business
+        level != Level::Tmpdir || child.is_some()
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::atom::Atom;
+use crate::emerge_log::LogEvent;
+
+/// number of past builds per package used for estimates
+/// older builds are less representative of current build times
+const MAX_SAMPLES: usize = 5;
+
+/// per-package build durations from emerge.log, similar to `qlop`
+#[derive(Default)]
+pub(crate) struct BuildHistory {
+    /// durations of the most recent builds per "category/package"
+    durations: HashMap<String, Vec<Duration>>,
+
+    /// start times of merges without completion yet per "category/package-version"
+    started: HashMap<String, Duration>,
+}
+
+impl BuildHistory {
+    /// feed a log event into the history
+    pub(crate) fn record(&mut self, event: &LogEvent) {
+        match event {
+            LogEvent::Started { time, atom, .. } => {
+                self.started.insert(atom.to_string(), *time);
+            }
+            LogEvent::Completed { time, atom } => {
+                let Some(start) = self.started.remove(&atom.to_string()) else {
+                    return;
+                };
+                let samples = self.durations.entry(package_key(atom)).or_default();
+                samples.push(time.saturating_sub(start));
+                if samples.len() > MAX_SAMPLES {
+                    samples.remove(0);
+                }
+            }
+            LogEvent::Merging { .. }
+            | LogEvent::Cleaning { .. }
+            | LogEvent::Unmerging { .. }
+            | LogEvent::Unmerged { .. } => (),
+        }
+    }
+
+    /// expected build duration of a package
+    pub(crate) fn estimate(&self, category: &str, package: &str) -> Option<Duration> {
+        let samples = self.durations.get(&format!("{}/{}", category, package))?;
+        mean(samples.iter())
+    }
+
+    /// expected build duration of any package
+    /// used for merge list entries we don't know yet
+    pub(crate) fn average(&self) -> Option<Duration> {
+        mean(self.durations.values().flatten())
+    }
+}
+
+/// history key of a package, versions don't matter
+fn package_key(atom: &Atom) -> String {
+    format!("{}/{}", atom.category, atom.package)
+}
+
+/// arithmetic mean, None if empty
+fn mean<'a>(durations: impl Iterator<Item = &'a Duration>) -> Option<Duration> {
+    let (sum, count) = durations.fold((Duration::ZERO, 0u32), |(sum, count), d| {
+        (sum + *d, count + 1)
+    });
+    sum.checked_div(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emerge_log::parse_line;
+
+    /// feed the emerge.log lines of one build
+    fn build(history: &mut BuildHistory, cpv: &str, start: u64, end: u64) {
+        for line in [
+            format!("{}:  >>> emerge (1 of 1) {} to /", start, cpv),
+            format!("{}:  ::: completed emerge (1 of 1) {} to /", end, cpv),
+        ] {
+            history.record(&parse_line(&line).unwrap());
+        }
+    }
+
+    #[test]
+    fn recent_builds() {
+        let mut history = BuildHistory::default();
+        // the two oldest builds are forgotten
+        for (i, duration) in [1000, 900, 10, 20, 30, 40, 50].into_iter().enumerate() {
+            let start = i as u64 * 10000;
+            build(&mut history, "dev-libs/foo-1.0", start, start + duration);
+        }
+        assert_eq!(
+            history.estimate("dev-libs", "foo"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(history.estimate("dev-libs", "bar"), None);
+    }
+
+    #[test]
+    fn versions_and_average() {
+        let mut history = BuildHistory::default();
+        build(&mut history, "dev-libs/foo-1.0", 0, 100);
+        build(&mut history, "dev-libs/foo-1.1", 1000, 1300);
+        build(&mut history, "dev-lang/bar-2", 2000, 2800);
+        assert_eq!(
+            history.estimate("dev-libs", "foo"),
+            Some(Duration::from_secs(200))
+        );
+        assert_eq!(history.average(), Some(Duration::from_secs(400)));
+    }
+
+    #[test]
+    fn unfinished_builds() {
+        let mut history = BuildHistory::default();
+        assert_eq!(history.average(), None);
+
+        // started but never completed, e.g. failed
+        let line = "100:  >>> emerge (1 of 1) dev-libs/foo-1.0 to /";
+        history.record(&parse_line(line).unwrap());
+        assert_eq!(history.estimate("dev-libs", "foo"), None);
+
+        // completed without a start in the log
+        let line = "200:  ::: completed emerge (1 of 1) dev-libs/bar-1.0 to /";
+        history.record(&parse_line(line).unwrap());
+        assert_eq!(history.estimate("dev-libs", "bar"), None);
+        assert_eq!(history.average(), None);
+    }
+}
@@ -1,31 +1,253 @@
+mod api;
+mod atom;
+mod build_log;
+mod build_progress;
+mod cli;
+mod config;
+mod dbus;
+mod emerge_log;
+mod emerge_mode;
+mod fetch;
+mod fswatch;
+mod history;
+mod phase;
 mod portage_info;
+mod privacy;
+mod proc_connector;
 mod rpchandler;
+mod session;
+mod sink;
+mod status;
+mod template;
 mod watcher;
 
-use tokio::sync::mpsc;
+use std::process::ExitCode;
+
+use clap::Parser;
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+use tokio::sync::watch;
 use tokio::task::JoinSet;
 
+use crate::api::StatusServer;
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::emerge_mode::EmergeMode;
+use crate::portage_info::ebuild_version;
 use crate::rpchandler::RPCHandler;
+use crate::session::{format_duration, unix_now};
+use crate::sink::{JsonLinesSink, NotificationSink, UnixSocketSink, run_sink};
 use crate::watcher::{ActiveJobs, EbuildProcWatcher};
 
-/// Discord API client ID
-const CLIENT_ID: &str = "1367276666665041960";
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-// process refresh intervals in seconds
-const REFRESH_INTERVAL_WAITING: u64 = 5;
-const REFRESH_INTERVAL_ACTIVE: u64 = 1;
+    let config = match cli.load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
 
-#[tokio::main]
-async fn main() {
-    let (tx, rx) = mpsc::channel::<ActiveJobs>(1);
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(config).await,
+        Command::Status => status(&config),
+        Command::Check => check(&config),
+    }
+}
+
+/// watch emerge and update the presence until something dies
+async fn run(config: Config) -> ExitCode {
+    let (tx, rx) = watch::channel(ActiveJobs::new());
 
     let mut tasks = JoinSet::new();
 
-    let watcher = EbuildProcWatcher::new(tx);
+    // fails if another instance is running
+    if config.api.enabled {
+        match StatusServer::bind(&config.api, config.privacy.clone(), rx.clone()) {
+            Ok(server) => {
+                tasks.spawn(server.run());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let watcher = EbuildProcWatcher::new(tx, &config.watcher);
     tasks.spawn(watcher.start());
 
-    let rpchandler = RPCHandler::new(rx);
-    tasks.spawn(rpchandler.start());
+    if config.sinks.discord {
+        match RPCHandler::new(
+            &config.discord.client_id,
+            config.presence.clone(),
+            config.privacy.clone(),
+        ) {
+            Ok(rpchandler) => {
+                tasks.spawn(run_sink(rpchandler, rx.clone()));
+            }
+            Err(e) => eprintln!("Error creating Discord client: {}", e),
+        }
+    }
+
+    if let Some(ref jsonl) = config.sinks.jsonl {
+        match JsonLinesSink::new(&jsonl.path).await {
+            Ok(sink) => {
+                tasks.spawn(run_sink(sink, rx.clone()));
+            }
+            Err(e) => eprintln!("Error creating JSON lines writer: {}", e),
+        }
+    }
+
+    if let Some(ref socket) = config.sinks.socket {
+        tasks.spawn(run_sink(UnixSocketSink::new(&socket.path), rx.clone()));
+    }
+
+    if let Some(ref notify) = config.sinks.notify {
+        tasks.spawn(run_sink(NotificationSink::new(notify), rx.clone()));
+    }
+
+    // the watcher only stops once every sink dropped its receiver
+    drop(rx);
+
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Err(e)) = result {
+            eprintln!("{}", e);
+        }
+    }
+
+    ExitCode::FAILURE
+}
+
+/// print currently detected jobs
+fn status(config: &Config) -> ExitCode {
+    // nobody listens, we just want a single scan
+    let (tx, _rx) = watch::channel(ActiveJobs::new());
+    let watcher = EbuildProcWatcher::new(tx, &config.watcher);
+
+    let job_trees = match watcher.snapshot() {
+        Ok(job_trees) => job_trees,
+        Err(e) => {
+            eprintln!("Error reading processes: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if job_trees.is_empty() {
+        println!("No emerge running");
+        return ExitCode::SUCCESS;
+    }
+
+    for (master, session) in &job_trees {
+        let command = match session.mode {
+            EmergeMode::Ebuild => "ebuild",
+            _ => "emerge",
+        };
+        print!(
+            "{} {} ({}): running for {}",
+            command,
+            master,
+            session.mode.label(),
+            format_duration(session.elapsed())
+        );
+        if let Some(ebuild) = &session.ebuild {
+            print!(", {}", ebuild.display());
+        }
+        if session.is_resolving() {
+            print!(", calculating dependencies");
+        }
+        if let Some(progress) = session.progress {
+            print!(", package {} of {}", progress.current, progress.total);
+        }
+        match session.estimated_end {
+            Some(end) if end > unix_now() => {
+                println!(", about {} left", format_duration(end - unix_now()))
+            }
+            _ => println!(),
+        }
+        for record in &session.packages {
+            let state = match (&record.failed, record.end_time) {
+                (Some(phase), _) => format!("failed in {}", phase),
+                (None, Some(_)) => String::from("done"),
+                (None, None) => String::from("running"),
+            };
+            println!(
+                "  {}/{}-{}{} ({}, {})",
+                record.category,
+                record.package,
+                record.version,
+                repo_suffix(record.repository.as_deref()),
+                state,
+                format_duration(record.elapsed())
+            );
+        }
+        let merge = session.merge_job().map(|job| (master, job));
+        for (pid, job) in session.jobs.iter().chain(merge) {
+            let origin = match job.binary {
+                true => String::from(", binary package"),
+                false => match job.build_log.as_ref().and_then(|log| log.progress()) {
+                    Some(percent) => format!(", {}%", percent),
+                    None => String::new(),
+                },
+            };
+            println!(
+                "  job {} {}/{}-{}{} ({}{})",
+                pid,
+                job.category,
+                job.package,
+                job.version,
+                repo_suffix(job.repository.as_deref()),
+                job.phase,
+                origin
+            );
+            if let Some(line) = job.build_log.as_ref().and_then(|log| log.last_line()) {
+                println!("    {}", line);
+            }
+        }
+        for (pid, fetch) in &session.fetches {
+            println!("  fetch {} {} ({})", pid, fetch.file, fetch.progress());
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// check everything needed for `run` works
+/// the config was already validated while loading
+fn check(config: &Config) -> ExitCode {
+    let mut ok = true;
+
+    println!("Config: OK");
+
+    match DiscordIpcClient::new(&config.discord.client_id)
+        .and_then(|mut client| client.connect().and_then(|_| client.close()))
+    {
+        Ok(_) => println!("Discord: OK"),
+        Err(e) => {
+            println!("Discord: FAILED ({})", e);
+            ok = false;
+        }
+    }
+
+    match ebuild_version() {
+        Ok(version) => println!("Portage: OK ({})", version),
+        Err(e) => {
+            println!("Portage: FAILED ({})", e);
+            ok = false;
+        }
+    }
+
+    match ok {
+        true => ExitCode::SUCCESS,
+        false => ExitCode::FAILURE,
+    }
+}
 
-    tasks.join_all().await;
+/// `::gentoo` like portage prints after a package, empty if unknown
+fn repo_suffix(repository: Option<&str>) -> String {
+    repository
+        .map(|repository| format!("::{}", repository))
+        .unwrap_or_default()
 }
@@ -0,0 +1,276 @@
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+
+/// ebuild phase as passed to ebuild.sh
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Phase {
+    Pretend,
+    Setup,
+    Nofetch,
+    Unpack,
+    Prepare,
+    Configure,
+    Compile,
+    Test,
+    Install,
+    Instprep,
+    Package,
+    Preinst,
+    Merge,
+    Postinst,
+    Prerm,
+    Unmerge,
+    Postrm,
+    Config,
+    Clean,
+
+    /// anything portage adds later, keeps the raw name
+    Unknown(String),
+}
+
+impl Phase {
+    /// all known phases in the order portage runs them
+    pub(crate) const KNOWN: [Phase; 19] = [
+        Phase::Pretend,
+        Phase::Setup,
+        Phase::Nofetch,
+        Phase::Unpack,
+        Phase::Prepare,
+        Phase::Configure,
+        Phase::Compile,
+        Phase::Test,
+        Phase::Install,
+        Phase::Instprep,
+        Phase::Package,
+        Phase::Preinst,
+        Phase::Merge,
+        Phase::Postinst,
+        Phase::Prerm,
+        Phase::Unmerge,
+        Phase::Postrm,
+        Phase::Config,
+        Phase::Clean,
+    ];
+
+    /// parse the ebuild.sh argument, unknown names are kept as they are
+    pub(crate) fn from_name(name: &str) -> Self {
+        Self::KNOWN
+            .into_iter()
+            .find(|phase| phase.as_str() == name)
+            .unwrap_or_else(|| Phase::Unknown(String::from(name)))
+    }
+
+    /// name as passed to ebuild.sh
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Phase::Pretend => "pretend",
+            Phase::Setup => "setup",
+            Phase::Nofetch => "nofetch",
+            Phase::Unpack => "unpack",
+            Phase::Prepare => "prepare",
+            Phase::Configure => "configure",
+            Phase::Compile => "compile",
+            Phase::Test => "test",
+            Phase::Install => "install",
+            Phase::Instprep => "instprep",
+            Phase::Package => "package",
+            Phase::Preinst => "preinst",
+            Phase::Merge => "merge",
+            Phase::Postinst => "postinst",
+            Phase::Prerm => "prerm",
+            Phase::Unmerge => "unmerge",
+            Phase::Postrm => "postrm",
+            Phase::Config => "config",
+            Phase::Clean => "clean",
+            Phase::Unknown(name) => name,
+        }
+    }
+
+    /// human readable activity
+    pub(crate) fn label(&self) -> &str {
+        match self {
+            Phase::Pretend => "Checking requirements",
+            Phase::Setup => "Setting up",
+            Phase::Nofetch => "Waiting for a manual download",
+            Phase::Unpack => "Unpacking sources",
+            Phase::Prepare => "Preparing sources",
+            Phase::Configure => "Configuring",
+            Phase::Compile => "Compiling",
+            Phase::Test => "Running tests",
+            Phase::Install => "Installing",
+            Phase::Instprep => "Preparing the image",
+            Phase::Package => "Creating a binary package",
+            Phase::Preinst => "Preparing to merge",
+            Phase::Merge => "Merging",
+            Phase::Postinst => "Finishing the merge",
+            Phase::Prerm => "Preparing to unmerge",
+            Phase::Unmerge => "Unmerging",
+            Phase::Postrm => "Finishing the unmerge",
+            Phase::Config => "Configuring the installed package",
+            Phase::Clean => "Cleaning up",
+            Phase::Unknown(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Phase {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// phase a portage helper script runs, from its arguments like
+/// `/usr/lib/portage/python3.12/ebuild.sh compile` or
+/// `/usr/lib/portage/python3.12/misc-functions.sh preinst_mask preinst_sfperms`
+pub(crate) fn script_phase(args: &[&str]) -> Option<Phase> {
+    let (script, rest) = args.split_first()?;
+    let name = Path::new(script.trim_matches('\'')).file_name()?.to_str()?;
+    let first = rest.first()?.trim_matches('\'');
+    match name {
+        "ebuild.sh" => Some(Phase::from_name(first)),
+        "misc-functions.sh" => Some(misc_functions_phase(first)),
+        _ => None,
+    }
+}
+
+/// misc-functions.sh runs QA checks and packaging around the real phases
+fn misc_functions_phase(function: &str) -> Phase {
+    match function {
+        "install_qa_check" | "__dyn_instprep" => Phase::Instprep,
+        "__dyn_package" => Phase::Package,
+        f if f.starts_with("preinst") => Phase::Preinst,
+        f if f.starts_with("postinst") => Phase::Postinst,
+        f => Phase::Unknown(String::from(f)),
+    }
+}
+
+/// parse the process title portage gives phase processes like
+/// `[sys-kernel/gentoo-sources-6.12.1] sandbox /usr/lib/portage/python3.12/ebuild.sh compile`
+/// or for phases outside the sandbox like pkg_postinst
+/// `[dev-libs/foo-1.0] bash -c /usr/lib/portage/python3.12/ebuild.sh postinst`
+/// returns the cpv and phase
+pub(crate) fn parse_title(title: &str) -> Option<(&str, Phase)> {
+    let args: Vec<&str> = title.split_ascii_whitespace().collect();
+    let cpv = args.first()?.strip_prefix('[')?.strip_suffix(']')?;
+    let script = match &args[1..] {
+        ["sandbox", script @ ..] | ["fakeroot", script @ ..] | ["bash", "-c", script @ ..] => {
+            script
+        }
+        _ => return None,
+    };
+    Some((cpv, script_phase(script)?))
+}
+
+/// parse the message portage prints when a phase dies like
+/// ` * ERROR: dev-libs/foo-1.0::gentoo failed (compile phase):`
+/// returns the cpv, the repository if given and the phase
+pub(crate) fn parse_failure(line: &str) -> Option<(&str, Option<&str>, Phase)> {
+    let rest = line.trim_start().trim_start_matches("* ");
+    let rest = rest.strip_prefix("ERROR: ")?;
+    let (package, rest) = rest.split_once(" failed (")?;
+    let (phase, _) = rest.split_once(" phase)")?;
+    let (cpv, repository) = match package.split_once("::") {
+        Some((cpv, repository)) => (cpv, Some(repository)),
+        None => (package, None),
+    };
+    Some((cpv, repository, Phase::from_name(phase)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for phase in Phase::KNOWN {
+            assert_eq!(Phase::from_name(phase.as_str()), phase);
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        let phase = Phase::from_name("frobnicate");
+        assert_eq!(phase, Phase::Unknown(String::from("frobnicate")));
+        assert_eq!(phase.as_str(), "frobnicate");
+        assert_eq!(phase.label(), "frobnicate");
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(
+            parse_title(
+                "[sys-kernel/cachyos-kernel-6.15.1] sandbox /usr/lib/portage/pypy3.11/ebuild.sh compile"
+            ),
+            Some(("sys-kernel/cachyos-kernel-6.15.1", Phase::Compile))
+        );
+        assert_eq!(
+            parse_title(
+                "[dev-libs/foo-1.0] bash -c /usr/lib/portage/python3.12/ebuild.sh postinst"
+            ),
+            Some(("dev-libs/foo-1.0", Phase::Postinst))
+        );
+        assert_eq!(
+            parse_title(
+                "[dev-libs/foo-1.0] bash -c '/usr/lib/portage/python3.12/misc-functions.sh' preinst_mask preinst_sfperms"
+            ),
+            Some(("dev-libs/foo-1.0", Phase::Preinst))
+        );
+        assert_eq!(
+            parse_title(
+                "[dev-libs/foo-1.0] fakeroot /usr/lib/portage/python3.12/ebuild.sh package"
+            ),
+            Some(("dev-libs/foo-1.0", Phase::Package))
+        );
+    }
+
+    #[test]
+    fn not_titles() {
+        for title in [
+            "bash /usr/lib/portage/python3.12/ebuild.sh compile",
+            "[dev-libs/foo-1.0] sandbox",
+            "[dev-libs/foo-1.0] bash -c make -j8",
+            "[dev-libs/foo-1.0] sudo /usr/lib/portage/python3.12/ebuild.sh compile",
+        ] {
+            assert_eq!(parse_title(title), None, "{}", title);
+        }
+    }
+
+    #[test]
+    fn failures() {
+        assert_eq!(
+            parse_failure(" * ERROR: dev-qt/qtbase-6.9.1::gentoo failed (compile phase):"),
+            Some(("dev-qt/qtbase-6.9.1", Some("gentoo"), Phase::Compile))
+        );
+        assert_eq!(
+            parse_failure("ERROR: dev-libs/foo-1.0 failed (prepare phase):"),
+            Some(("dev-libs/foo-1.0", None, Phase::Prepare))
+        );
+        assert_eq!(
+            parse_failure(" * ERROR: dev-libs/foo-1.0::gentoo failed:"),
+            None
+        );
+        assert_eq!(parse_failure(" * Applying foo-1.0-fix.patch ..."), None);
+    }
+
+    #[test]
+    fn scripts() {
+        let phase = |s: &str| script_phase(&s.split_ascii_whitespace().collect::<Vec<_>>());
+        assert_eq!(
+            phase("/usr/lib/portage/python3.12/ebuild.sh prerm"),
+            Some(Phase::Prerm)
+        );
+        assert_eq!(
+            phase("/usr/lib/portage/python3.12/misc-functions.sh install_qa_check"),
+            Some(Phase::Instprep)
+        );
+        assert_eq!(phase("/usr/lib/portage/python3.12/ebuild.sh"), None);
+        assert_eq!(phase("/usr/bin/make install"), None);
+    }
+}
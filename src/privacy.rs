@@ -0,0 +1,329 @@
+use serde::Deserialize;
+
+use crate::config::PrivacyConfig;
+use crate::fetch::FetchJob;
+use crate::watcher::{ActiveJobs, EbuildJob};
+
+/// how much of a job may be shown, ordered from least to most private
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Visibility {
+    /// category, package and version
+    #[default]
+    Show,
+
+    /// only the category
+    Category,
+
+    /// nothing but the phase
+    Anonymous,
+}
+
+/// `category/package` or `category/package::repository`
+/// with `*` matching any number of characters
+/// e.g. `acme-*/*`, `*/internal-tool` or `*/*::acme-overlay`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct AtomPattern {
+    category: String,
+    package: String,
+    repository: Option<String>,
+}
+
+impl AtomPattern {
+    /// whether the package matches
+    /// packages from an unknown repository never match a repository pattern,
+    /// they are shown anonymously instead
+    fn matches(&self, category: &str, package: &str, repository: Option<&str>) -> bool {
+        let repository_matches = match (&self.repository, repository) {
+            (None, _) => true,
+            (Some(pattern), Some(repository)) => glob_match(pattern, repository),
+            (Some(_), None) => false,
+        };
+        repository_matches
+            && glob_match(&self.category, category)
+            && glob_match(&self.package, package)
+    }
+}
+
+impl TryFrom<String> for AtomPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, String> {
+        let (atom, repository) = match pattern.split_once("::") {
+            Some((atom, repository)) if !repository.is_empty() => {
+                (atom, Some(String::from(repository)))
+            }
+            Some(_) => (pattern.as_str(), None), // rejected below
+            None => (pattern.as_str(), None),
+        };
+        match atom.split_once('/') {
+            Some((category, package))
+                if !category.is_empty()
+                    && !package.is_empty()
+                    && !package.contains('/')
+                    && !package.contains(':') =>
+            {
+                Ok(Self {
+                    category: String::from(category),
+                    package: String::from(package),
+                    repository,
+                })
+            }
+            _ => Err(format!(
+                "invalid package pattern \"{}\", expected category/package or category/package::repository",
+                pattern
+            )),
+        }
+    }
+}
+
+impl PrivacyConfig {
+    /// how much of the job may be shown, None if it must not be shown at all
+    pub(crate) fn visibility(&self, job: &EbuildJob) -> Option<Visibility> {
+        self.package_visibility(&job.category, &job.package, job.repository.as_deref())
+    }
+
+    /// how much of the download may be shown, None if it must not be shown at all
+    /// downloads of unknown packages are only shown if nothing is filtered
+    pub(crate) fn fetch_visibility(&self, fetch: &FetchJob) -> Option<Visibility> {
+        match (&fetch.category, &fetch.package) {
+            (Some(category), Some(package)) => {
+                self.package_visibility(category, package, fetch.repository.as_deref())
+            }
+            _ if self.redact.is_empty() && self.drop.is_empty() => Some(self.mode),
+            _ => Some(Visibility::Anonymous),
+        }
+    }
+
+    fn package_visibility(
+        &self,
+        category: &str,
+        package: &str,
+        repository: Option<&str>,
+    ) -> Option<Visibility> {
+        if self.is_dropped(category, package, repository) {
+            return None;
+        }
+        if self
+            .redact
+            .iter()
+            .any(|pattern| pattern.matches(category, package, repository))
+        {
+            return Some(Visibility::Anonymous);
+        }
+        // the package might be from a filtered repository
+        if repository.is_none() && self.has_repository_patterns() {
+            return Some(Visibility::Anonymous);
+        }
+        Some(self.mode)
+    }
+
+    /// whether any rule depends on the repository
+    fn has_repository_patterns(&self) -> bool {
+        self.redact
+            .iter()
+            .chain(&self.drop)
+            .any(|pattern| pattern.repository.is_some())
+    }
+
+    /// remove dropped jobs, downloads and packages
+    /// so they don't show up in job counts, phases or estimates
+    pub(crate) fn apply(&self, job_trees: &ActiveJobs) -> ActiveJobs {
+        if self.drop.is_empty() {
+            return job_trees.clone();
+        }
+
+        let mut job_trees = job_trees.clone();
+        for session in job_trees.values_mut() {
+            session.jobs.retain(|_, job| self.visibility(job).is_some());
+            session
+                .fetches
+                .retain(|_, fetch| self.fetch_visibility(fetch).is_some());
+            session
+                .merge
+                .take_if(|merge| self.visibility(merge).is_none());
+            session
+                .failure
+                .take_if(|failure| self.visibility(&failure.job).is_none());
+            session.packages.retain(|record| {
+                !self.is_dropped(
+                    &record.category,
+                    &record.package,
+                    record.repository.as_deref(),
+                )
+            });
+            session.estimate_end();
+        }
+        // the summary counts only what's left
+        for session in job_trees.values_mut() {
+            let (merged, failed) = session.package_counts();
+            if let Some(summary) = &mut session.summary {
+                summary.merged = merged;
+                summary.failed = failed;
+            }
+        }
+        job_trees.retain(|_, session| session.is_shown());
+        job_trees
+    }
+
+    /// whether the package must not be shown at all
+    fn is_dropped(&self, category: &str, package: &str, repository: Option<&str>) -> bool {
+        self.drop
+            .iter()
+            .any(|pattern| pattern.matches(category, package, repository))
+    }
+}
+
+/// match `text` against `pattern` where `*` is any number of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+
+    // each middle part has to appear in order, leftmost match is enough
+    for part in parts {
+        match text.find(part) {
+            Some(i) => text = &text[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    text.len() >= last.len() && text.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emerge_mode::EmergeMode;
+    use crate::session::{EmergeSession, PackageRecord};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// wget download of a distfile for a package
+    fn fetch(category: &str, package: &str) -> FetchJob {
+        let cmdline = [
+            "wget",
+            "-O",
+            "/nonexistent/foo-1.0.tar.gz.__download__",
+            "http://x",
+        ]
+        .map(String::from);
+        let environ = HashMap::from([
+            (String::from("CATEGORY"), String::from(category)),
+            (String::from("PN"), String::from(package)),
+        ]);
+        FetchJob::from_proc(&cmdline, &environ, Duration::ZERO).unwrap()
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("dev-lang", "dev-lang"));
+        assert!(!glob_match("dev-lang", "dev-langs"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("acme-*", "acme-tools"));
+        assert!(!glob_match("acme-*", "x-acme-tools"));
+        assert!(glob_match("*-internal", "foo-internal"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXcYYb"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn pattern() {
+        let pattern = AtomPattern::try_from(String::from("acme-*/*")).unwrap();
+        assert!(pattern.matches("acme-libs", "secret", None));
+        assert!(pattern.matches("acme-libs", "secret", Some("gentoo")));
+        assert!(!pattern.matches("dev-libs", "secret", None));
+
+        for s in ["acme", "/foo", "acme/", "a/b/c", "a/b::", "::acme", "a/b:c"] {
+            assert!(AtomPattern::try_from(String::from(s)).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn repository_pattern() {
+        let pattern = AtomPattern::try_from(String::from("*/*::acme-*")).unwrap();
+        assert!(pattern.matches("dev-libs", "foo", Some("acme-overlay")));
+        assert!(!pattern.matches("dev-libs", "foo", Some("gentoo")));
+        assert!(!pattern.matches("dev-libs", "foo", None));
+    }
+
+    #[test]
+    fn unknown_repository() {
+        let config = PrivacyConfig {
+            drop: vec![AtomPattern::try_from(String::from("*/*::acme-overlay")).unwrap()],
+            ..PrivacyConfig::default()
+        };
+        assert_eq!(
+            config.package_visibility("dev-libs", "foo", Some("gentoo")),
+            Some(Visibility::Show)
+        );
+        assert_eq!(
+            config.package_visibility("dev-libs", "foo", Some("acme-overlay")),
+            None
+        );
+        assert_eq!(
+            config.package_visibility("dev-libs", "foo", None),
+            Some(Visibility::Anonymous)
+        );
+
+        // without repository rules the repository doesn't matter
+        let config = PrivacyConfig {
+            drop: vec![AtomPattern::try_from(String::from("acme-*/*")).unwrap()],
+            ..PrivacyConfig::default()
+        };
+        assert_eq!(
+            config.package_visibility("dev-libs", "foo", None),
+            Some(Visibility::Show)
+        );
+    }
+
+    #[test]
+    fn dropped_downloads() {
+        let config = PrivacyConfig {
+            drop: vec![AtomPattern::try_from(String::from("acme-*/*")).unwrap()],
+            ..PrivacyConfig::default()
+        };
+        let mut session = EmergeSession::new(Duration::ZERO, EmergeMode::Build);
+        session.insert_fetch(1, fetch("acme-libs", "secret"));
+        session.insert_fetch(2, fetch("dev-libs", "foo"));
+
+        let job_trees = config.apply(&ActiveJobs::from([(10, session)]));
+        let fetches = &job_trees[&10].fetches;
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[&2].package.as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn dropped_estimates() {
+        let config = PrivacyConfig {
+            drop: vec![AtomPattern::try_from(String::from("acme-*/*")).unwrap()],
+            ..PrivacyConfig::default()
+        };
+        let record = |category: &str, estimated_end: u64| PackageRecord {
+            category: String::from(category),
+            package: String::from("foo"),
+            version: "1.0".parse().unwrap(),
+            repository: None,
+            start_time: Duration::ZERO,
+            end_time: None,
+            estimated_end: Some(Duration::from_secs(estimated_end)),
+            failed: None,
+            merged: false,
+        };
+        let mut session = EmergeSession::new(Duration::ZERO, EmergeMode::Build);
+        session.packages = vec![record("dev-libs", 600), record("acme-libs", 3600)];
+        session.estimate_end();
+        assert_eq!(session.estimated_end, Some(Duration::from_secs(3600)));
+
+        let job_trees = config.apply(&ActiveJobs::from([(10, session)]));
+        assert_eq!(job_trees[&10].estimated_end, Some(Duration::from_secs(600)));
+    }
+}
@@ -0,0 +1,259 @@
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use tokio::io::Interest;
+use tokio::io::unix::AsyncFd;
+
+// constants from linux/connector.h and linux/cn_proc.h
+// these are not exposed by libc
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+/// size of struct nlmsghdr
+const NLMSG_HDRLEN: usize = 16;
+
+/// size of struct cn_msg without data
+const CN_MSG_HDRLEN: usize = 20;
+
+/// offset of event_data in struct proc_event
+/// after what, cpu and timestamp_ns
+const PROC_EVENT_DATA: usize = 16;
+
+/// process events we care about
+#[derive(Debug, PartialEq)]
+pub(crate) enum ProcEvent {
+    /// process called exec(), carries the pid
+    Exec(u32),
+
+    /// process exited, carries the pid
+    Exit(u32),
+}
+
+/// subscription to the kernel proc connector
+/// requires CAP_NET_ADMIN
+pub(crate) struct ProcConnector {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl ProcConnector {
+    /// open a netlink socket and start listening for process events
+    pub(crate) fn new() -> Result<Self, String> {
+        // SAFETY: plain syscall, the returned fd is checked and owned right away
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if raw < 0 {
+            return Err(format!("socket: {}", io::Error::last_os_error()));
+        }
+        // SAFETY: raw is a valid fd nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: all-zero is a valid sockaddr_nl
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = CN_IDX_PROC;
+
+        // SAFETY: addr is a properly initialized sockaddr_nl of the given size
+        let res = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(format!("bind: {}", io::Error::last_os_error()));
+        }
+
+        // nlmsghdr + cn_msg + PROC_CN_MCAST_LISTEN
+        let len = NLMSG_HDRLEN + CN_MSG_HDRLEN + 4;
+        let mut msg = Vec::with_capacity(len);
+        // nlmsghdr: len, type, flags, seq, pid
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&std::process::id().to_ne_bytes());
+        // cn_msg: idx, val, seq, ack, len, flags
+        msg.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+        msg.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&4u16.to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        // data
+        msg.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+
+        // SAFETY: msg is a valid buffer of msg.len() bytes
+        let res = unsafe { libc::send(fd.as_raw_fd(), msg.as_ptr().cast(), msg.len(), 0) };
+        if res < 0 {
+            return Err(format!("send: {}", io::Error::last_os_error()));
+        }
+
+        let fd = AsyncFd::with_interest(fd, Interest::READABLE).map_err(|e| e.to_string())?;
+        Ok(Self { fd })
+    }
+
+    /// wait for the next batch of process events
+    /// an Err of kind OutOfMemory (ENOBUFS) means events were dropped
+    pub(crate) async fn recv(&self) -> io::Result<Vec<ProcEvent>> {
+        let mut buf = [0u8; 4096];
+        let len = self
+            .fd
+            .async_io(Interest::READABLE, |fd| {
+                // SAFETY: buf is a valid writable buffer of buf.len() bytes
+                let res =
+                    unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+                if res < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(res as usize)
+            })
+            .await?;
+
+        Ok(parse_events(&buf[..len]))
+    }
+}
+
+/// read a native endian u32 at `offset`
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+/// split a datagram into netlink messages and extract process events
+fn parse_events(buf: &[u8]) -> Vec<ProcEvent> {
+    let mut events = Vec::new();
+
+    let mut offset = 0;
+    while let Some(msg_len) = read_u32(buf, offset) {
+        let msg_len = msg_len as usize;
+        if msg_len < NLMSG_HDRLEN || offset + msg_len > buf.len() {
+            break; // truncated
+        }
+
+        let event = offset + NLMSG_HDRLEN + CN_MSG_HDRLEN;
+        let data = event + PROC_EVENT_DATA;
+
+        // exec and exit both start with process_pid, process_tgid
+        // only care about whole processes, not threads
+        if let (Some(what), Some(pid), Some(tgid)) = (
+            read_u32(buf, event),
+            read_u32(buf, data),
+            read_u32(buf, data + 4),
+        ) && pid == tgid
+        {
+            match what {
+                PROC_EVENT_EXEC => events.push(ProcEvent::Exec(pid)),
+                PROC_EVENT_EXIT => events.push(ProcEvent::Exit(pid)),
+                _ => (),
+            }
+        }
+
+        // messages are aligned to 4 bytes
+        offset += (msg_len + 3) & !3;
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// netlink message with a proc_event, `data` follows process_pid and process_tgid
+    /// `extra` bytes of junk make the length unaligned
+    fn message(what: u32, pid: u32, tgid: u32, data: &[u8], extra: usize) -> Vec<u8> {
+        let mut event = Vec::new();
+        event.extend_from_slice(&what.to_ne_bytes());
+        event.extend_from_slice(&3u32.to_ne_bytes()); // cpu
+        event.extend_from_slice(&123456789u64.to_ne_bytes()); // timestamp_ns
+        event.extend_from_slice(&pid.to_ne_bytes());
+        event.extend_from_slice(&tgid.to_ne_bytes());
+        event.extend_from_slice(data);
+        event.resize(event.len() + extra, 0xff);
+
+        let len = NLMSG_HDRLEN + CN_MSG_HDRLEN + event.len();
+        let mut msg = Vec::new();
+        // nlmsghdr: len, type, flags, seq, pid
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&7u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        // cn_msg: idx, val, seq, ack, len, flags
+        msg.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+        msg.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+        msg.extend_from_slice(&7u32.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&(event.len() as u16).to_ne_bytes());
+        msg.extend_from_slice(&0u16.to_ne_bytes());
+        msg.extend_from_slice(&event);
+        // padding to the next message
+        msg.resize((msg.len() + 3) & !3, 0);
+        msg
+    }
+
+    fn exec(pid: u32, tgid: u32) -> Vec<u8> {
+        message(PROC_EVENT_EXEC, pid, tgid, &[], 0)
+    }
+
+    /// exit_code, exit_signal, parent_pid and parent_tgid follow
+    fn exit(pid: u32, tgid: u32) -> Vec<u8> {
+        let data: Vec<u8> = [256u32, 17, 1, 1]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        message(PROC_EVENT_EXIT, pid, tgid, &data, 0)
+    }
+
+    #[test]
+    fn exec_and_exit() {
+        assert_eq!(parse_events(&exec(1234, 1234)), [ProcEvent::Exec(1234)]);
+        assert_eq!(parse_events(&exit(1234, 1234)), [ProcEvent::Exit(1234)]);
+
+        // several messages in one datagram, one of them unaligned
+        let mut buf = message(PROC_EVENT_EXEC, 10, 10, &[], 1);
+        buf.extend(exit(20, 20));
+        assert_eq!(
+            parse_events(&buf),
+            [ProcEvent::Exec(10), ProcEvent::Exit(20)]
+        );
+    }
+
+    #[test]
+    fn threads_and_other_events() {
+        assert!(parse_events(&exec(1235, 1234)).is_empty());
+        assert!(parse_events(&exit(1235, 1234)).is_empty());
+
+        // PROC_EVENT_FORK
+        let fork = message(0x0000_0001, 1234, 1234, &[0; 8], 0);
+        assert!(parse_events(&fork).is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut buf = exec(10, 10);
+        buf.extend(exit(20, 20));
+
+        // the second message is cut off
+        let cut = buf.len() - 8;
+        assert_eq!(parse_events(&buf[..cut]), [ProcEvent::Exec(10)]);
+
+        // not even a whole header
+        assert!(parse_events(&buf[..3]).is_empty());
+        assert!(parse_events(&[]).is_empty());
+
+        // a length too small for a netlink header
+        let mut bogus = buf.clone();
+        bogus[..4].copy_from_slice(&4u32.to_ne_bytes());
+        assert!(parse_events(&bogus).is_empty());
+    }
+}
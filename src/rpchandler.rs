@@ -1,202 +1,375 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::time::Duration;
 
-use discord_rich_presence::activity::{Assets, Timestamps};
+use discord_rich_presence::activity::{Assets, Party, Timestamps};
 use discord_rich_presence::{DiscordIpc, DiscordIpcClient, activity::Activity};
-use tokio::sync::mpsc::Receiver;
 use tokio::time::sleep;
 
-use crate::CLIENT_ID;
+use crate::config::{PresenceConfig, PrivacyConfig};
+use crate::emerge_mode::EmergeMode;
+use crate::phase::Phase;
 use crate::portage_info::ebuild_version;
+use crate::privacy::Visibility;
+use crate::session::unix_now;
+use crate::sink::PresenceSink;
+use crate::template::Context;
 use crate::watcher::ActiveJobs;
 
 pub(crate) struct RPCHandler {
-    /// sender for updates
-    rx: Receiver<ActiveJobs>,
+    /// Discord IPC connection
+    client: DiscordIpcClient,
+
+    /// presence text and assets
+    presence: PresenceConfig,
+
+    /// what may be shown
+    privacy: PrivacyConfig,
+
+    /// whether the activity is currently cleared
+    cleared: bool,
+
+    /// large image text, refreshed per session
+    version_str: Option<String>,
 }
 
 impl RPCHandler {
     /// create new RPCHandler
-    pub(crate) fn new(rx: Receiver<ActiveJobs>) -> Self {
-        Self { rx }
+    pub(crate) fn new(
+        client_id: &str,
+        presence: PresenceConfig,
+        privacy: PrivacyConfig,
+    ) -> Result<Self, String> {
+        let client = DiscordIpcClient::new(client_id).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            client,
+            presence,
+            privacy,
+            cleared: true,
+            version_str: None,
+        })
     }
 
-    /// start listening and sending updates
-    pub(crate) async fn start(mut self) -> Result<(), String> {
-        let mut client = match DiscordIpcClient::new(CLIENT_ID) {
-            Ok(client) => client,
-            Err(e) => return Err(e.to_string()),
-        };
+    /// (re)connect until Discord answers
+    async fn reconnect(&mut self) {
+        // stupid lazy Box<dyn std::error::Error>> is not Send....
+        while let Err(e) = self.client.reconnect().map_err(|e| e.to_string()) {
+            eprintln!("Connecting to Discord failed: {}", e);
+            eprintln!("Retrying in 5 seconds");
+            sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+impl PresenceSink for RPCHandler {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
 
+    async fn connect(&mut self) -> Result<(), String> {
         // stupid lazy Box<dyn std::error::Error>> is not Send....
-        while let Err(e) = client.connect().map_err(|e| e.to_string()) {
+        while let Err(e) = self.client.connect().map_err(|e| e.to_string()) {
             eprintln!("Connecting to Discord failed: {}", e);
             eprintln!("Retrying in 5 seconds");
             sleep(Duration::from_secs(5)).await;
         }
 
         println!("Connected to Discord");
+        Ok(())
+    }
+
+    /// send an activity update
+    async fn update(&mut self, job_trees: &ActiveJobs) -> Result<(), String> {
+        let job_trees = &self.privacy.apply(job_trees);
 
-        let mut cleared = true;
-        let mut version_str: Option<String> = None;
-        while let Some(job_trees) = self.rx.recv().await {
-            #[cfg(debug_assertions)]
-            println!("Handler received update");
-
-            // track if we should reconnect
-            let mut should_reconnect = false;
-
-            // clear on empty set
-            if job_trees.is_empty() {
-                // don't clear multiple times
-                if cleared {
-                    continue;
-                }
-
-                if let Err(e) = client.clear_activity() {
-                    eprintln!("Error clearing activity: {}", e);
-                } else {
-                    cleared = true;
-                }
-                continue;
+        // clear on empty set
+        if job_trees.is_empty() {
+            // don't clear multiple times
+            if self.cleared {
+                return Ok(());
             }
 
-            // first iteration after clearing
-            // per-session tasks should go here
-            if cleared {
-                version_str = match ebuild_version() {
+            if let Err(e) = self.client.clear_activity() {
+                eprintln!("Error clearing activity: {}", e);
+            } else {
+                self.cleared = true;
+            }
+            return Ok(());
+        }
+
+        // first iteration after clearing
+        // per-session tasks should go here
+        if self.cleared {
+            self.version_str = match self.presence.large_text {
+                Some(ref text) => Some(text.clone()),
+                None => match ebuild_version() {
                     Ok(ver) => Some(ver),
                     Err(e) => {
                         eprintln!("Error getting ebuild version: {}", e);
                         None
                     }
-                };
-                cleared = false;
-            }
+                },
+            };
+            self.cleared = false;
+        }
 
-            // now redefine jobs to a combination of all trees
-            let mut jobs = Vec::new();
-            for job_tree in job_trees.values() {
-                for job in job_tree.values() {
-                    jobs.push(job);
-                }
+        // now redefine jobs to a combination of all trees
+        let mut jobs = Vec::new();
+        for job_tree in job_trees.values() {
+            for job in job_tree.jobs.values().chain(job_tree.merge_job()) {
+                jobs.push(job);
             }
+        }
 
-            // first line
-            let info = match jobs.len() {
-                0 => String::from("No Jobs Running"),
-                1 => format!(
-                    "{}/{}-{}",
-                    jobs[0].category, jobs[0].package, jobs[0].version
-                ),
-                _ => format!("{} Jobs Running", jobs.len()),
-            };
+        // most advanced merge list position of all sessions
+        let progress = job_trees
+            .values()
+            .filter_map(|session| session.progress)
+            .max_by_key(|progress| progress.current);
+
+        // emerge not building anything like --sync, oldest first
+        let mode = job_trees
+            .values()
+            .filter(|session| session.mode != EmergeMode::Build)
+            .min_by_key(|session| session.start_time)
+            .map(|session| session.mode);
+
+        // nothing started yet in any session
+        let resolving = job_trees.values().all(|session| session.is_resolving());
+
+        // phase summary like "compile (2), install (1)"
+        // in the order portage runs them
+        let mut counter: BTreeMap<&Phase, u32> = BTreeMap::new();
+        for job in &jobs {
+            *counter.entry(&job.phase).or_default() += 1;
+        }
+        let phases_vec: Vec<String> = counter
+            .iter()
+            .map(|(phase, count)| format!("{} ({})", phase, count))
+            .collect();
+
+        // oldest download, only shown while no job runs
+        let fetch = job_trees
+            .values()
+            .filter_map(|session| session.oldest_fetch())
+            .min_by_key(|fetch| fetch.create_time)
+            .filter(|_| jobs.is_empty());
+
+        // latest build failure, only shown while no job runs
+        let failure = job_trees
+            .values()
+            .filter_map(|session| session.failure.as_ref())
+            .max_by_key(|failure| failure.time)
+            .filter(|_| jobs.is_empty());
+
+        // summary of the last finished emerge, once no other one runs
+        let summary = job_trees
+            .values()
+            .filter_map(|session| session.summary.as_ref())
+            .max_by_key(|summary| summary.time)
+            .filter(|_| failure.is_none() && job_trees.values().all(|s| s.finished));
+
+        // strictest visibility of all jobs, the failure or the download
+        // dropped ones were already removed from the table
+        let visibility = match (failure, fetch) {
+            (Some(failure), _) => self.privacy.visibility(&failure.job),
+            (None, Some(fetch)) => self.privacy.fetch_visibility(fetch),
+            (None, None) => jobs
+                .iter()
+                .filter_map(|job| self.privacy.visibility(job))
+                .max(),
+        }
+        .unwrap_or_default();
+
+        let context = Context {
+            job: match failure {
+                Some(failure) => Some(&failure.job),
+                None => (jobs.len() == 1).then(|| jobs[0]),
+            },
+            fetch,
+            visibility,
+            job_count: jobs.len(),
+            phases: phases_vec.join(", "),
+            progress,
+            elapsed: match summary {
+                Some(summary) => Some(summary.elapsed),
+                None => job_trees.values().map(|session| session.elapsed()).max(),
+            },
+            mode: mode.unwrap_or_default(),
+            root: job_trees
+                .values()
+                .filter_map(|session| session.root.as_deref())
+                .next()
+                .unwrap_or("/"),
+            ebuild: job_trees
+                .values()
+                .filter(|session| !session.jobs.is_empty())
+                .find_map(|session| session.ebuild.as_deref()),
+            summary,
+        };
 
-            // phase info of running jobs
-            let phases: Option<String>;
-            let mut phase_icon: Option<&str> = None;
-            match jobs.len() {
-                0 => phases = None,
-                1 => {
-                    phases = Some(format!("Phase: {}", jobs[0].phase));
-                    phase_icon = match jobs[0].phase.as_str() {
-                        "unpack" => Some("phase_unpack"),
-                        "prepare" => Some("phase_prepare"),
-                        "configure" => Some("phase_configure"),
-                        "compile" => Some("phase_compile"),
-                        "install" => Some("phase_install"),
-                        _ => None,
-                    };
-                }
-                _ => {
-                    let mut counter: HashMap<String, u32> = HashMap::new();
-                    for job in &jobs {
-                        match counter.get(&job.phase) {
-                            Some(curr) => {
-                                counter.insert(job.phase.clone(), curr + 1);
-                            }
-                            None => {
-                                counter.insert(job.phase.clone(), 1);
-                            }
-                        }
+        let compile_progress = context
+            .job
+            .and_then(|job| job.build_log.as_ref())
+            .and_then(|log| log.progress());
+
+        // first and second line
+        // state is None if emerge doesn't have jobs running
+        let templates = &self.presence.templates;
+        let (info, phases) = match jobs.len() {
+            0 if failure.is_some() => (
+                match visibility {
+                    Visibility::Show => templates.failed_details.render(&context),
+                    _ => templates.anonymous_failed_details.render(&context),
+                },
+                None,
+            ),
+            0 if summary.is_some() => (
+                templates.summary_details.render(&context),
+                Some(templates.summary_state.render(&context)),
+            ),
+            0 if resolving => (templates.resolving_details.render(&context), None),
+            0 if fetch.is_some() => (
+                match visibility {
+                    Visibility::Show => templates.fetch_details.render(&context),
+                    _ => templates.anonymous_fetch_details.render(&context),
+                },
+                Some(templates.fetch_state.render(&context)),
+            ),
+            0 => match (mode, progress) {
+                (Some(_), _) => (templates.mode_details.render(&context), None),
+                (None, Some(_)) => (templates.idle_progress_details.render(&context), None),
+                (None, None) => (templates.idle_details.render(&context), None),
+            },
+            1 => (
+                match visibility {
+                    Visibility::Show if context.ebuild.is_some() => {
+                        templates.ebuild_details.render(&context)
                     }
-                    let mut phases_vec = Vec::new();
-                    for (phase, count) in counter {
-                        if count > 0 {
-                            phases_vec.push(format!("{} ({})", phase, count));
-                        }
+                    Visibility::Show => templates.single_details.render(&context),
+                    Visibility::Category => templates.category_details.render(&context),
+                    Visibility::Anonymous => templates.anonymous_details.render(&context),
+                },
+                match (&jobs[0].phase, jobs[0].binary) {
+                    (Phase::Merge, _) => Some(templates.merge_state.render(&context)),
+                    (Phase::Compile, false) if compile_progress.is_some() => {
+                        Some(templates.compile_state.render(&context))
                     }
-                    phases = Some(format!("Phases: {}", phases_vec.join(", ")));
-                }
+                    (_, true) => Some(templates.binary_state.render(&context)),
+                    (_, false) => Some(templates.single_state.render(&context)),
+                },
+            ),
+            _ => (
+                templates.multi_details.render(&context),
+                Some(templates.multi_state.render(&context)),
+            ),
+        };
+        let phases = phases.filter(|phases| !phases.is_empty());
+
+        // small image with the phase, binary package, failure, summary,
+        // download or dependency calculation
+        let small_image = match jobs.len() {
+            0 if failure.is_some() => {
+                Some((self.presence.failed_icon.as_str(), Some(info.as_str())))
+            }
+            0 if summary.is_some() => {
+                Some((self.presence.summary_icon.as_str(), Some(info.as_str())))
             }
+            0 if resolving => Some((self.presence.resolving_icon.as_str(), Some(info.as_str()))),
+            0 if fetch.is_some() => Some((self.presence.fetch_icon.as_str(), Some(info.as_str()))),
+            1 if jobs[0].binary => Some((self.presence.binary_icon.as_str(), phases.as_deref())),
+            // hovering shows what the phase does
+            1 => {
+                let phase = &jobs[0].phase;
+                let icon = match self.presence.phase_icons.get(phase.as_str()) {
+                    Some(icon) => icon,
+                    None => &self.presence.unknown_phase_icon,
+                };
+                Some((icon.as_str(), Some(phase.label())))
+            }
+            _ => None,
+        };
 
-            // timestamp
-            let start_time = match jobs.len() {
-                0 => None,
-                1 => Some(jobs[0].create_time.as_secs() as i64),
-                _ => {
-                    let mut min: i64 = jobs[0].create_time.as_secs() as i64;
-                    for job in &jobs[1..] {
-                        let this = job.create_time.as_secs() as i64;
-                        if this < min {
-                            min = this;
-                        }
-                    }
-                    Some(min)
-                }
-            };
+        // timestamp of the oldest emerge session
+        // so the timer doesn't restart with each phase
+        // finished sessions have nothing left to time
+        let start_time = job_trees
+            .values()
+            .filter(|session| !session.finished)
+            .map(|session| session.start_time.as_secs() as i64)
+            .min();
 
-            let mut activity = Activity::new().details(&info);
+        // Discord rejects empty text, so templates may leave out a line
+        let mut activity = Activity::new();
+        if !info.is_empty() {
+            activity = activity.details(&info);
+        }
 
-            // state (2nd line) is None if emerge doesn't have jobs running
-            if let Some(ref phases) = phases {
-                activity = activity.state(phases);
-            }
+        // state (2nd line) is None if emerge doesn't have jobs running
+        if let Some(ref phases) = phases {
+            activity = activity.state(phases);
 
-            // start time is only set if jobs are running
-            // I think by default this will use time the activity was set
-            if let Some(time) = start_time {
-                activity = activity.timestamps(Timestamps::new().start(time));
+            // shown as "(12 of 87)" after the state
+            if let Some(progress) = progress {
+                activity = activity
+                    .party(Party::new().size([progress.current as i32, progress.total as i32]));
             }
+        }
 
-            // add assets
-            let mut assets = Assets::new();
-            assets = assets.large_image("gentoo_box");
-            if let Some(ref version_str) = version_str {
-                assets = assets.large_text(version_str);
-            }
-            if let Some(phase_icon) = phase_icon {
-                assets = assets.small_image(phase_icon);
-                if let Some(ref phases) = phases {
-                    assets = assets.small_text(phases);
-                }
-            }
-            activity = activity.assets(assets);
-
-            #[cfg(debug_assertions)]
-            println!(
-                "Sending update: state=\"{}\", details=\"{}\", start_time=\"{}\"",
-                phases.clone().unwrap_or(String::from("None")),
-                &info,
-                &start_time.unwrap_or(-1)
-            );
-
-            if let Err(e) = client.set_activity(activity) {
-                eprintln!("Error setting activity: {}", e);
-                should_reconnect = true;
+        // countdown to the estimated end of the merge list
+        // only if it's still in the future, overdue builds just count up
+        let end_time = job_trees
+            .values()
+            .filter(|session| !session.finished)
+            .filter_map(|session| session.estimated_end)
+            .max()
+            .map(|time| time.as_secs() as i64)
+            .filter(|time| *time > unix_now().as_secs() as i64);
+
+        // start time is only set if emerge is running
+        // I think by default this will use time the activity was set
+        if let Some(time) = start_time {
+            let mut timestamps = Timestamps::new().start(time);
+            if let Some(end) = end_time {
+                timestamps = timestamps.end(end);
             }
+            activity = activity.timestamps(timestamps);
+        }
 
-            // if we encountered errors on the way we should probably reconnect...
-            if should_reconnect {
-                eprintln!("Encountered an error talking to Discord... trying to reconnect");
-                while let Err(e) = client.reconnect().map_err(|e| e.to_string()) {
-                    eprintln!("Connecting to Discord failed: {}", e);
-                    eprintln!("Retrying in 5 seconds");
-                    sleep(Duration::from_secs(5)).await;
-                }
+        // add assets
+        let mut assets = Assets::new();
+        assets = assets.large_image(&self.presence.large_image);
+        if let Some(ref version_str) = self.version_str {
+            assets = assets.large_text(version_str);
+        }
+        if let Some((icon, text)) = small_image {
+            assets = assets.small_image(icon);
+            if let Some(text) = text {
+                assets = assets.small_text(text);
             }
         }
+        activity = activity.assets(assets);
+
+        #[cfg(debug_assertions)]
+        println!(
+            "Sending update: state=\"{}\", details=\"{}\", start_time=\"{}\"",
+            phases.clone().unwrap_or(String::from("None")),
+            &info,
+            &start_time.unwrap_or(-1)
+        );
+
+        // if we encountered errors on the way we should probably reconnect...
+        // stupid lazy Box<dyn std::error::Error>> is not Send....
+        if let Err(e) = self
+            .client
+            .set_activity(activity)
+            .map_err(|e| e.to_string())
+        {
+            eprintln!("Error setting activity: {}", e);
+            eprintln!("Encountered an error talking to Discord... trying to reconnect");
+            self.reconnect().await;
+        }
 
-        Err(String::from("Connection to process watcher died"))
+        Ok(())
     }
 }
@@ -0,0 +1,758 @@
+use psutil::Pid;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::atom::{Atom, Version};
+use crate::emerge_log::LogEvent;
+use crate::emerge_mode::EmergeMode;
+use crate::fetch::FetchJob;
+use crate::history::BuildHistory;
+use crate::phase::Phase;
+use crate::watcher::EbuildJob;
+
+/// merge list position as logged by emerge, e.g. `(12 of 87)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct MergeProgress {
+    /// 1-based index of the package being merged
+    pub(crate) current: u32,
+
+    /// number of packages in the merge list
+    pub(crate) total: u32,
+}
+
+/// a package seen during an emerge session
+#[derive(Clone, PartialEq)]
+pub(crate) struct PackageRecord {
+    /// ebuild category
+    pub(crate) category: String,
+
+    /// ebuild package
+    pub(crate) package: String,
+
+    /// ebuild version
+    pub(crate) version: Version,
+
+    /// repository the ebuild comes from, if known
+    pub(crate) repository: Option<String>,
+
+    /// first phase start in unix epoch duration
+    pub(crate) start_time: Duration,
+
+    /// last phase end in unix epoch duration
+    /// None while a phase is running
+    pub(crate) end_time: Option<Duration>,
+
+    /// expected end from build history in unix epoch duration
+    pub(crate) estimated_end: Option<Duration>,
+
+    /// phase the package failed in
+    pub(crate) failed: Option<Phase>,
+
+    /// emerge logged the package as completed
+    pub(crate) merged: bool,
+}
+
+impl PackageRecord {
+    /// time spent on this package so far
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.end_time
+            .unwrap_or_else(unix_now)
+            .saturating_sub(self.start_time)
+    }
+
+    /// whether the job belongs to this package
+    fn matches(&self, job: &EbuildJob) -> bool {
+        self.category == job.category && self.package == job.package && self.version == job.version
+    }
+
+    /// whether this is the package
+    fn is(&self, atom: &Atom) -> bool {
+        self.category == atom.category
+            && self.package == atom.package
+            && self.version == atom.version
+    }
+}
+
+/// a job that died, kept for a while to show what went wrong
+#[derive(Clone, PartialEq)]
+pub(crate) struct BuildFailure {
+    /// the job in the phase that failed
+    pub(crate) job: EbuildJob,
+
+    /// when the failure was noticed in unix epoch duration
+    pub(crate) time: Duration,
+}
+
+/// what an emerge session did, made when the master exits
+#[derive(Clone, PartialEq)]
+pub(crate) struct SessionSummary {
+    /// packages emerge completed
+    pub(crate) merged: usize,
+
+    /// packages that failed
+    pub(crate) failed: usize,
+
+    /// time from the master start to its exit
+    pub(crate) elapsed: Duration,
+
+    /// when the master exited in unix epoch duration
+    pub(crate) time: Duration,
+}
+
+/// everything that happened under one emerge master process
+#[derive(Clone, PartialEq)]
+pub(crate) struct EmergeSession {
+    /// emerge master creation time in unix epoch duration
+    pub(crate) start_time: Duration,
+
+    /// what emerge was asked to do
+    pub(crate) mode: EmergeMode,
+
+    /// running jobs as {"ebuild job pid": {job...}}
+    pub(crate) jobs: HashMap<Pid, EbuildJob>,
+
+    /// running downloads as {"fetcher pid": {fetch...}}
+    pub(crate) fetches: HashMap<Pid, FetchJob>,
+
+    /// whether any job or download was seen
+    /// dependencies are resolved by then
+    pub(crate) started: bool,
+
+    /// packages in the order they were first seen
+    pub(crate) packages: Vec<PackageRecord>,
+
+    /// merge list position from emerge.log
+    pub(crate) progress: Option<MergeProgress>,
+
+    /// ebuild file of an `ebuild(1)` session
+    pub(crate) ebuild: Option<PathBuf>,
+
+    /// ROOT packages are merged into, from emerge.log
+    pub(crate) root: Option<String>,
+
+    /// emerge itself merging files or cleaning up after a package
+    /// there's no ebuild process for this, it comes from emerge.log
+    pub(crate) merge: Option<EbuildJob>,
+
+    /// expected end of the whole merge list in unix epoch duration
+    pub(crate) estimated_end: Option<Duration>,
+
+    /// average build duration from history, for packages still queued
+    average_build: Option<Duration>,
+
+    /// last build failure until it was shown long enough
+    pub(crate) failure: Option<BuildFailure>,
+
+    /// summary shown for a while after the master exited
+    pub(crate) summary: Option<SessionSummary>,
+
+    /// the emerge master exited
+    /// the session only stays to show its failure or summary
+    pub(crate) finished: bool,
+}
+
+impl EmergeSession {
+    /// create a session for an emerge master started at `start_time`
+    pub(crate) fn new(start_time: Duration, mode: EmergeMode) -> Self {
+        Self {
+            start_time,
+            mode,
+            jobs: HashMap::new(),
+            fetches: HashMap::new(),
+            started: false,
+            packages: Vec::new(),
+            progress: None,
+            ebuild: None,
+            root: None,
+            merge: None,
+            estimated_end: None,
+            average_build: None,
+            failure: None,
+            summary: None,
+            finished: false,
+        }
+    }
+
+    /// whether the package was seen in this session
+    pub(crate) fn contains(&self, atom: &Atom) -> bool {
+        self.packages.iter().any(|p| p.is(atom))
+    }
+
+    /// emerge is still calculating dependencies
+    /// i.e. it's building but nothing started yet
+    pub(crate) fn is_resolving(&self) -> bool {
+        self.mode == EmergeMode::Build && !self.started && self.progress.is_none()
+    }
+
+    /// the merge step as a job, unless a phase of the same package
+    /// like pkg_postinst is running and shows up as a job anyway
+    pub(crate) fn merge_job(&self) -> Option<&EbuildJob> {
+        self.merge.as_ref().filter(|merge| {
+            !self.jobs.values().any(|job| {
+                job.category == merge.category
+                    && job.package == merge.package
+                    && job.version == merge.version
+            })
+        })
+    }
+
+    /// whether emerge.log says the package is being merged from a binary package
+    pub(crate) fn is_merging_binary(&self, atom: &Atom) -> bool {
+        self.merge.as_ref().is_some_and(|merge| {
+            merge.binary
+                && merge.category == atom.category
+                && merge.package == atom.package
+                && merge.version == atom.version
+        })
+    }
+
+    /// apply an emerge.log line that belongs to this session
+    /// returns whether the session changed
+    pub(crate) fn apply_log(&mut self, event: &LogEvent) -> bool {
+        let old = (
+            self.progress,
+            self.root.clone(),
+            self.merge.clone(),
+            self.failure.clone(),
+            self.packages.clone(),
+        );
+        match event {
+            LogEvent::Started { progress, root, .. } => {
+                self.progress = Some(*progress);
+                self.root = Some(root.clone());
+            }
+            LogEvent::Merging { time, atom, binary } => {
+                self.set_merge(atom, Phase::Merge, *binary, *time)
+            }
+            LogEvent::Cleaning { time, atom } => {
+                let binary = self.merge.as_ref().is_some_and(|merge| merge.binary);
+                self.set_merge(atom, Phase::Clean, binary, *time)
+            }
+            LogEvent::Completed { time, atom } => {
+                // unmerging the replaced version is part of the merge
+                self.merge.take_if(|merge| {
+                    merge.phase == Phase::Unmerge
+                        || (merge.category == atom.category
+                            && merge.package == atom.package
+                            && merge.version == atom.version)
+                });
+
+                // done unless a phase of it is somehow still running
+                if let Some(record) = self.packages.iter_mut().rev().find(|p| p.is(atom)) {
+                    record.merged = true;
+                    if record.end_time.is_none()
+                        && !self.jobs.values().any(|job| record.matches(job))
+                    {
+                        record.end_time = Some(*time);
+                    }
+                }
+            }
+            LogEvent::Unmerging { time, atom } => {
+                // no package record, the version is on its way out
+                self.merge = Some(EbuildJob {
+                    category: atom.category.clone(),
+                    package: atom.package.clone(),
+                    version: atom.version.clone(),
+                    repository: None,
+                    phase: Phase::Unmerge,
+                    binary: false,
+                    build_log: None,
+                    create_time: *time,
+                });
+                self.started = true;
+            }
+            LogEvent::Unmerged { atom, .. } => {
+                self.merge.take_if(|merge| {
+                    merge.phase == Phase::Unmerge
+                        && merge.category == atom.category
+                        && merge.package == atom.package
+                        && merge.version == atom.version
+                });
+            }
+        }
+        old != (
+            self.progress,
+            self.root.clone(),
+            self.merge.clone(),
+            self.failure.clone(),
+            self.packages.clone(),
+        )
+    }
+
+    /// emerge started merging or cleaning up after a package
+    fn set_merge(&mut self, atom: &Atom, phase: Phase, binary: bool, time: Duration) {
+        // the package isn't done until the merge is
+        // the log has no repository, but earlier phases might
+        let mut repository = None;
+        match self.packages.iter_mut().rev().find(|p| p.is(atom)) {
+            Some(record) => {
+                record.end_time = None;
+                repository = record.repository.clone();
+            }
+            None => self.packages.push(PackageRecord {
+                category: atom.category.clone(),
+                package: atom.package.clone(),
+                version: atom.version.clone(),
+                repository: None,
+                start_time: time,
+                end_time: None,
+                estimated_end: None,
+                failed: None,
+                merged: false,
+            }),
+        }
+
+        self.merge = Some(EbuildJob {
+            category: atom.category.clone(),
+            package: atom.package.clone(),
+            version: atom.version.clone(),
+            repository,
+            phase,
+            binary,
+            build_log: None,
+            create_time: time,
+        });
+        self.started = true;
+    }
+
+    /// remember a job as failed, the phase of the job is the failed one
+    fn fail(&mut self, job: EbuildJob, time: Duration) {
+        if let Some(record) = self.packages.iter_mut().rev().find(|p| p.matches(&job)) {
+            record.failed = Some(job.phase.clone());
+            record.end_time.get_or_insert(time);
+        }
+        self.failure = Some(BuildFailure { job, time });
+    }
+
+    /// number of merged and failed packages
+    pub(crate) fn package_counts(&self) -> (usize, usize) {
+        let merged = self
+            .packages
+            .iter()
+            .filter(|p| p.merged && p.failed.is_none())
+            .count();
+        let failed = self.packages.iter().filter(|p| p.failed.is_some()).count();
+        (merged, failed)
+    }
+
+    /// the emerge master exited, so did all jobs
+    /// sessions that got to any package get a summary
+    pub(crate) fn finish(&mut self) {
+        let pids: Vec<Pid> = self
+            .jobs
+            .keys()
+            .chain(self.fetches.keys())
+            .cloned()
+            .collect();
+        for pid in pids {
+            self.remove_job(&pid);
+        }
+        self.merge = None;
+        self.finished = true;
+
+        if !self.packages.is_empty() {
+            let (merged, failed) = self.package_counts();
+            let time = unix_now();
+            self.summary = Some(SessionSummary {
+                merged,
+                failed,
+                elapsed: time.saturating_sub(self.start_time),
+                time,
+            });
+        }
+    }
+
+    /// forget the failure once it was shown for `grace_period`
+    /// returns whether it was cleared
+    pub(crate) fn expire_failure(&mut self, grace_period: Duration) -> bool {
+        self.failure
+            .take_if(|failure| unix_now().saturating_sub(failure.time) >= grace_period)
+            .is_some()
+    }
+
+    /// forget the summary once it was shown for `duration`
+    /// returns whether it was cleared
+    pub(crate) fn expire_summary(&mut self, duration: Duration) -> bool {
+        self.summary
+            .take_if(|summary| unix_now().saturating_sub(summary.time) >= duration)
+            .is_some()
+    }
+
+    /// whether the session is running or still has something to show
+    pub(crate) fn is_shown(&self) -> bool {
+        !self.finished || self.failure.is_some() || self.summary.is_some()
+    }
+
+    /// the download that has been running longest
+    pub(crate) fn oldest_fetch(&self) -> Option<&FetchJob> {
+        self.fetches.values().min_by_key(|fetch| fetch.create_time)
+    }
+
+    /// time since the emerge master started
+    pub(crate) fn elapsed(&self) -> Duration {
+        unix_now().saturating_sub(self.start_time)
+    }
+
+    /// recalculate expected end times from build history
+    pub(crate) fn update_estimates(&mut self, history: &BuildHistory) {
+        self.average_build = history.average();
+        for record in &mut self.packages {
+            record.estimated_end = history
+                .estimate(&record.category, &record.package)
+                .or(self.average_build)
+                .map(|duration| record.start_time + duration);
+        }
+        self.estimate_end();
+    }
+
+    /// recalculate the expected end of the merge list from the package estimates
+    /// e.g. after packages were removed from the session
+    pub(crate) fn estimate_end(&mut self) {
+        // the merge list is done when the running packages are done
+        // plus whatever is still queued after them
+        let running_end = self
+            .packages
+            .iter()
+            .filter(|record| record.end_time.is_none())
+            .map(|record| record.estimated_end)
+            .max()
+            .flatten();
+        let last_end = self
+            .packages
+            .iter()
+            .filter_map(|record| record.end_time)
+            .max();
+
+        self.estimated_end = match self.progress {
+            Some(progress) => {
+                let queued = progress.total.saturating_sub(progress.current);
+                let base = running_end.or(last_end);
+                match (base, self.average_build) {
+                    (Some(base), _) if queued == 0 => Some(base),
+                    (Some(base), Some(average)) => Some(base + average * queued),
+                    _ => None,
+                }
+            }
+            None => running_end,
+        };
+    }
+
+    /// add or update a job
+    /// returns whether the session changed
+    pub(crate) fn insert_job(&mut self, pid: Pid, job: EbuildJob) -> bool {
+        // if jobs are equal we don't want an update
+        if self.jobs.get(&pid) == Some(&job) {
+            return false;
+        }
+
+        // each phase is a new job, so a package that finished
+        // a phase before is simply continuing
+        match self.packages.iter_mut().rev().find(|p| p.matches(&job)) {
+            Some(record) => {
+                record.end_time = None;
+                if job.repository.is_some() {
+                    record.repository = job.repository.clone();
+                }
+            }
+            None => self.packages.push(PackageRecord {
+                category: job.category.clone(),
+                package: job.package.clone(),
+                version: job.version.clone(),
+                repository: job.repository.clone(),
+                start_time: job.create_time,
+                end_time: None,
+                estimated_end: None,
+                failed: None,
+                merged: false,
+            }),
+        }
+
+        self.jobs.insert(pid, job);
+        self.started = true;
+        true
+    }
+
+    /// add a download
+    pub(crate) fn insert_fetch(&mut self, pid: Pid, fetch: FetchJob) {
+        self.fetches.insert(pid, fetch);
+        self.started = true;
+    }
+
+    /// remove a job or download whose process exited
+    /// returns whether the session changed
+    pub(crate) fn remove_job(&mut self, pid: &Pid) -> bool {
+        if self.fetches.remove(pid).is_some() {
+            return true;
+        }
+
+        let Some(mut job) = self.jobs.remove(pid) else {
+            return false;
+        };
+
+        // the phase died if the rest of its log says so
+        let failed = job.build_log.as_mut().and_then(|build_log| {
+            build_log.sample();
+            build_log.failure().cloned()
+        });
+
+        // the package is done for now unless another job of it still runs
+        // or emerge is still merging it
+        if let Some(record) = self.packages.iter_mut().rev().find(|p| p.matches(&job))
+            && !self.jobs.values().any(|other| record.matches(other))
+            && !self
+                .merge
+                .as_ref()
+                .is_some_and(|merge| record.matches(merge))
+        {
+            record.end_time = Some(unix_now());
+        }
+
+        if let Some(phase) = failed {
+            let job = EbuildJob {
+                phase,
+                build_log: None,
+                ..job
+            };
+            self.fail(job, unix_now());
+        }
+
+        true
+    }
+}
+
+/// current time in unix epoch duration
+pub(crate) fn unix_now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
+
+/// human readable duration like `1h 02m 03s`
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match h {
+        0 => format!("{}m {:02}s", m, s),
+        _ => format!("{}h {:02}m {:02}s", h, m, s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emerge_log::parse_line;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// package that started at `start` and is still running
+    fn record(cpv: &str, start: u64) -> PackageRecord {
+        let atom: Atom = cpv.parse().unwrap();
+        PackageRecord {
+            category: atom.category,
+            package: atom.package,
+            version: atom.version,
+            repository: None,
+            start_time: secs(start),
+            end_time: None,
+            estimated_end: None,
+            failed: None,
+            merged: false,
+        }
+    }
+
+    /// foo took 100s, bar 300s, so 200s on average
+    fn history() -> BuildHistory {
+        let mut history = BuildHistory::default();
+        for line in [
+            "0:  >>> emerge (1 of 2) dev-libs/foo-1.0 to /",
+            "100:  ::: completed emerge (1 of 2) dev-libs/foo-1.0 to /",
+            "100:  >>> emerge (2 of 2) dev-libs/bar-1.0 to /",
+            "400:  ::: completed emerge (2 of 2) dev-libs/bar-1.0 to /",
+        ] {
+            history.record(&parse_line(line).unwrap());
+        }
+        history
+    }
+
+    fn new_session(packages: Vec<PackageRecord>, progress: Option<(u32, u32)>) -> EmergeSession {
+        let mut session = EmergeSession::new(secs(1000), EmergeMode::Build);
+        session.packages = packages;
+        session.progress = progress.map(|(current, total)| MergeProgress { current, total });
+        session
+    }
+
+    #[test]
+    fn estimates() {
+        let mut foo = record("dev-libs/foo-2.0", 1000);
+        foo.end_time = Some(secs(1090));
+        let packages = vec![
+            foo,
+            record("dev-libs/bar-2.0", 1100),
+            record("dev-libs/baz-1.0", 1100), // never built, the average is used
+        ];
+        let mut session = new_session(packages, Some((3, 5)));
+        session.update_estimates(&history());
+
+        let ends: Vec<_> = session.packages.iter().map(|p| p.estimated_end).collect();
+        assert_eq!(ends, [Some(secs(1100)), Some(secs(1400)), Some(secs(1300))]);
+        // the running packages end at 1400, then two more at 200s each
+        assert_eq!(session.estimated_end, Some(secs(1800)));
+    }
+
+    #[test]
+    fn last_in_list() {
+        let mut session = new_session(vec![record("dev-libs/foo-2.0", 1000)], Some((5, 5)));
+        session.update_estimates(&history());
+        assert_eq!(session.estimated_end, Some(secs(1100)));
+
+        // between packages the last one that ended is the base
+        session.packages[0].end_time = Some(secs(1050));
+        session.progress = Some(MergeProgress {
+            current: 4,
+            total: 5,
+        });
+        session.update_estimates(&history());
+        assert_eq!(session.estimated_end, Some(secs(1250)));
+    }
+
+    #[test]
+    fn without_history() {
+        // `ebuild` sessions have no merge list
+        let mut session = new_session(vec![record("dev-libs/foo-2.0", 1000)], None);
+        session.update_estimates(&history());
+        assert_eq!(session.estimated_end, Some(secs(1100)));
+
+        // nothing to guess the queued packages from
+        let mut session = new_session(vec![record("dev-libs/foo-2.0", 1000)], Some((1, 3)));
+        session.update_estimates(&BuildHistory::default());
+        assert_eq!(session.packages[0].estimated_end, None);
+        assert_eq!(session.estimated_end, None);
+    }
+
+    /// session that built foo and bar and failed on baz
+    /// emerge logs baz as completed anyway if the failure is in a pkg_ phase
+    fn finished_session() -> EmergeSession {
+        let mut session = new_session(
+            vec![
+                record("dev-libs/foo-1.0", 1000),
+                record("dev-libs/bar-1.0", 1100),
+                record("dev-libs/baz-1.0", 1200),
+                record("dev-libs/qux-1.0", 1300), // running when emerge died
+            ],
+            Some((4, 5)),
+        );
+        for line in [
+            "1100:  ::: completed emerge (1 of 5) dev-libs/foo-1.0 to /",
+            "1200:  ::: completed emerge (2 of 5) dev-libs/bar-1.0 to /",
+        ] {
+            session.apply_log(&parse_line(line).unwrap());
+        }
+        // the build log of pkg_postinst reported the failure
+        let baz: Atom = "dev-libs/baz-1.0".parse().unwrap();
+        let job = EbuildJob {
+            category: baz.category,
+            package: baz.package,
+            version: baz.version,
+            repository: None,
+            phase: Phase::Postinst,
+            binary: false,
+            build_log: None,
+            create_time: secs(1290),
+        };
+        session.fail(job, secs(1290));
+        let line = "1300:  ::: completed emerge (3 of 5) dev-libs/baz-1.0 to /";
+        session.apply_log(&parse_line(line).unwrap());
+        session.finish();
+        session
+    }
+
+    #[test]
+    fn summary() {
+        let session = finished_session();
+        assert_eq!(session.package_counts(), (2, 1));
+
+        let summary = session.summary.as_ref().unwrap();
+        assert_eq!((summary.merged, summary.failed), (2, 1));
+        assert_eq!(summary.elapsed, summary.time - secs(1000));
+        assert!(session.finished && session.is_shown());
+
+        // nothing to sum up
+        let mut session = new_session(Vec::new(), None);
+        session.finish();
+        assert!(session.summary.is_none());
+        assert!(!session.is_shown());
+    }
+
+    #[test]
+    fn summary_expiry() {
+        let mut session = finished_session();
+        session.failure = None;
+        assert!(!session.expire_summary(secs(15)));
+        assert!(session.is_shown());
+
+        session.summary.as_mut().unwrap().time = unix_now() - secs(15);
+        assert!(!session.expire_summary(secs(20)));
+        assert!(session.expire_summary(secs(15)));
+        assert!(session.summary.is_none());
+        assert!(!session.is_shown());
+    }
+
+    #[test]
+    fn summary_disabled() {
+        let mut session = finished_session();
+        assert!(session.expire_summary(Duration::ZERO));
+
+        // the failure still has its grace period
+        session.failure.as_mut().unwrap().time = unix_now();
+        assert!(session.is_shown());
+        assert!(!session.expire_failure(secs(30)));
+        session.failure.as_mut().unwrap().time = unix_now() - secs(30);
+        assert!(session.expire_failure(secs(30)));
+        assert!(!session.is_shown());
+    }
+
+    #[test]
+    fn merging_binary() {
+        let foo: Atom = "dev-libs/foo-1.0".parse().unwrap();
+        let mut session = new_session(vec![record("dev-libs/foo-1.0", 1000)], Some((1, 1)));
+        assert!(!session.is_merging_binary(&foo));
+
+        let line = "1100:  === (1 of 1) Merging Binary (dev-libs/foo-1.0::/var/cache/binpkgs/dev-libs/foo/foo-1.0-1.gpkg.tar)";
+        session.apply_log(&parse_line(line).unwrap());
+        assert!(session.is_merging_binary(&foo));
+        assert!(!session.is_merging_binary(&"dev-libs/foo-1.1".parse().unwrap()));
+
+        let line = "1110:  ::: completed emerge (1 of 1) dev-libs/foo-1.0 to /";
+        session.apply_log(&parse_line(line).unwrap());
+        assert!(!session.is_merging_binary(&foo));
+    }
+
+    #[test]
+    fn unmerging() {
+        let mut session = new_session(vec![record("dev-libs/foo-2.0", 1000)], Some((1, 1)));
+        let line = "1100:  === Unmerging... (dev-libs/foo-1.0)";
+        assert!(session.apply_log(&parse_line(line).unwrap()));
+        let merge = session.merge.as_ref().unwrap();
+        assert_eq!(merge.version.to_string(), "1.0");
+        assert_eq!(merge.phase, Phase::Unmerge);
+        assert_eq!(session.packages.len(), 1);
+
+        let line = "1105:  >>> unmerge success: dev-libs/foo-1.0";
+        assert!(session.apply_log(&parse_line(line).unwrap()));
+        assert!(session.merge.is_none());
+    }
+
+    #[test]
+    fn completed_record() {
+        // only the package record changes, there is no merge step to clear
+        let mut session = new_session(vec![record("dev-libs/foo-1.0", 1000)], Some((1, 1)));
+        let line = "1100:  ::: completed emerge (1 of 1) dev-libs/foo-1.0 to /";
+        assert!(session.apply_log(&parse_line(line).unwrap()));
+        assert!(session.packages[0].merged);
+        assert_eq!(session.packages[0].end_time, Some(secs(1100)));
+
+        // the same line again changes nothing
+        assert!(!session.apply_log(&parse_line(line).unwrap()));
+    }
+}
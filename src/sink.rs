@@ -0,0 +1,47 @@
+mod jsonl;
+mod notify;
+mod unix_socket;
+
+use std::future::Future;
+use tokio::sync::watch::Receiver;
+
+use crate::watcher::ActiveJobs;
+
+pub(crate) use jsonl::JsonLinesSink;
+pub(crate) use notify::NotificationSink;
+pub(crate) use unix_socket::UnixSocketSink;
+
+/// consumer of job updates, e.g. Discord or a status bar
+pub(crate) trait PresenceSink: Send {
+    /// name used in log messages
+    fn name(&self) -> &'static str;
+
+    /// set up the sink before the first update
+    fn connect(&mut self) -> impl Future<Output = Result<(), String>> + Send {
+        async { Ok(()) }
+    }
+
+    /// show the current state
+    fn update(&mut self, job_trees: &ActiveJobs)
+    -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// feed every watcher update into a sink until the watcher dies
+pub(crate) async fn run_sink(
+    mut sink: impl PresenceSink,
+    mut rx: Receiver<ActiveJobs>,
+) -> Result<(), String> {
+    sink.connect().await?;
+
+    while rx.changed().await.is_ok() {
+        #[cfg(debug_assertions)]
+        println!("{} received update", sink.name());
+
+        let job_trees = rx.borrow_and_update().clone();
+        if let Err(e) = sink.update(&job_trees).await {
+            eprintln!("Error updating {}: {}", sink.name(), e);
+        }
+    }
+
+    Err(String::from("Connection to process watcher died"))
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+
+use crate::sink::PresenceSink;
+use crate::status::Status;
+use crate::watcher::ActiveJobs;
+
+/// writes one JSON status object per update, to stdout or a file
+pub(crate) struct JsonLinesSink {
+    out: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+impl JsonLinesSink {
+    /// open the output, `-` means stdout
+    /// files are appended to
+    pub(crate) async fn new(path: &Path) -> Result<Self, String> {
+        let out: Box<dyn AsyncWrite + Send + Unpin> = match path.to_str() {
+            Some("-") => Box::new(io::stdout()),
+            _ => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| format!("Could not open {}: {}", path.display(), e))?,
+            ),
+        };
+
+        Ok(Self { out })
+    }
+}
+
+impl PresenceSink for JsonLinesSink {
+    fn name(&self) -> &'static str {
+        "JSON lines writer"
+    }
+
+    async fn update(&mut self, job_trees: &ActiveJobs) -> Result<(), String> {
+        let mut line = serde_json::to_string(&Status::new(job_trees)).map_err(|e| e.to_string())?;
+        line.push('\n');
+
+        self.out
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        self.out.flush().await.map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,377 @@
+use std::time::Duration;
+
+use crate::config::NotifyConfig;
+use crate::dbus::{MethodCall, SessionBus, Writer};
+use crate::session::{PackageRecord, format_duration};
+use crate::sink::PresenceSink;
+use crate::watcher::ActiveJobs;
+
+/// urgency hint values from the notification specification
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+/// desktop notification about something that happened
+#[derive(Debug, PartialEq)]
+struct Notification {
+    summary: String,
+    body: String,
+    icon: &'static str,
+    urgency: u8,
+}
+
+/// sends freedesktop notifications when emerge finishes,
+/// a package fails or a long build completes
+pub(crate) struct NotificationSink {
+    /// packages built at least this long get a notification when done
+    long_build_threshold: Duration,
+
+    /// connected on first use and again after errors
+    bus: Option<SessionBus>,
+
+    /// previous update to find what changed
+    last: ActiveJobs,
+}
+
+impl NotificationSink {
+    pub(crate) fn new(config: &NotifyConfig) -> Self {
+        Self {
+            long_build_threshold: Duration::from_secs(config.long_build_threshold),
+            bus: None,
+            last: ActiveJobs::new(),
+        }
+    }
+
+    /// hand a notification to the notification daemon
+    async fn send(&mut self, notification: &Notification) -> Result<(), String> {
+        let bus = match self.bus {
+            Some(ref mut bus) => bus,
+            None => self.bus.insert(SessionBus::connect().await?),
+        };
+
+        let mut body = Writer::default();
+        body.string("portpresence"); // app name
+        body.u32(0); // replaces nothing
+        body.string(notification.icon);
+        body.string(&notification.summary);
+        body.string(&notification.body);
+        body.array(4, |_| ()); // no actions
+        body.array(8, |w| {
+            w.open_struct();
+            w.string("urgency");
+            w.signature("y");
+            w.byte(notification.urgency);
+        });
+        body.i32(-1); // the server decides when it expires
+
+        let result = bus
+            .call(MethodCall {
+                destination: "org.freedesktop.Notifications",
+                path: "/org/freedesktop/Notifications",
+                interface: "org.freedesktop.Notifications",
+                member: "Notify",
+                signature: "susssasa{sv}i",
+                body: body.buf,
+            })
+            .await;
+
+        // reconnect next time, the bus or the daemon might have restarted
+        if result.is_err() {
+            self.bus = None;
+        }
+        result.map(|_| ())
+    }
+}
+
+impl PresenceSink for NotificationSink {
+    fn name(&self) -> &'static str {
+        "desktop notifications"
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        // not fatal, sending connects again
+        match SessionBus::connect().await {
+            Ok(bus) => self.bus = Some(bus),
+            Err(e) => eprintln!("Connecting to the session bus failed: {}", e),
+        }
+        Ok(())
+    }
+
+    async fn update(&mut self, job_trees: &ActiveJobs) -> Result<(), String> {
+        let notifications = changes(&self.last, job_trees, self.long_build_threshold);
+        self.last = job_trees.clone();
+
+        // one that fails shouldn't hold back the others
+        let mut failed = 0;
+        for notification in &notifications {
+            if let Err(e) = self.send(notification).await {
+                eprintln!(
+                    "Error sending notification \"{}\": {}",
+                    notification.summary, e
+                );
+                failed += 1;
+            }
+        }
+
+        match failed {
+            0 => Ok(()),
+            _ => Err(format!(
+                "{} of {} notifications failed",
+                failed,
+                notifications.len()
+            )),
+        }
+    }
+}
+
+/// what to notify about between two updates
+fn changes(
+    last: &ActiveJobs,
+    now: &ActiveJobs,
+    long_build_threshold: Duration,
+) -> Vec<Notification> {
+    let mut notifications = Vec::new();
+
+    for (master, session) in now {
+        // sessions show up before any package,
+        // so an unknown one was running before we started
+        let Some(previous) = last
+            .get(master)
+            .filter(|previous| previous.start_time == session.start_time)
+        else {
+            continue;
+        };
+
+        for record in &session.packages {
+            let before = previous.packages.iter().find(|p| same_package(p, record));
+
+            if let Some(phase) = &record.failed
+                && before.is_none_or(|before| before.failed.is_none())
+            {
+                notifications.push(Notification {
+                    summary: String::from("Build failed"),
+                    body: format!("{} failed in the {} phase", cpv(record), phase),
+                    icon: "dialog-error",
+                    urgency: URGENCY_CRITICAL,
+                });
+            }
+
+            if record.merged
+                && record.failed.is_none()
+                && before.is_none_or(|before| !before.merged)
+                && record.elapsed() >= long_build_threshold
+            {
+                notifications.push(Notification {
+                    summary: String::from("Build finished"),
+                    body: format!(
+                        "{} merged after {}",
+                        cpv(record),
+                        format_duration(record.elapsed())
+                    ),
+                    icon: "dialog-information",
+                    urgency: URGENCY_NORMAL,
+                });
+            }
+        }
+
+        // the summary might be expired already if it's not shown
+        if session.finished && !previous.finished && !session.packages.is_empty() {
+            let (merged, failed) = session.package_counts();
+            let elapsed = match &session.summary {
+                Some(summary) => summary.elapsed,
+                None => session.elapsed(),
+            };
+            notifications.push(finished(merged, failed, elapsed));
+        }
+    }
+
+    // sessions with nothing left to show vanish right away
+    for (master, session) in last {
+        let running = now
+            .get(master)
+            .is_some_and(|now| now.start_time == session.start_time);
+        if !running && !session.finished && !session.packages.is_empty() {
+            let (merged, failed) = session.package_counts();
+            notifications.push(finished(merged, failed, session.elapsed()));
+        }
+    }
+
+    notifications
+}
+
+/// emerge exited after building something
+fn finished(merged: usize, failed: usize, elapsed: Duration) -> Notification {
+    Notification {
+        summary: String::from("Emerge finished"),
+        body: format!(
+            "{} merged, {} failed after {}",
+            merged,
+            failed,
+            format_duration(elapsed)
+        ),
+        icon: match failed {
+            0 => "dialog-information",
+            _ => "dialog-warning",
+        },
+        urgency: URGENCY_NORMAL,
+    }
+}
+
+fn same_package(a: &PackageRecord, b: &PackageRecord) -> bool {
+    a.category == b.category && a.package == b.package && a.version == b.version
+}
+
+/// `cat/pkg-ver` of a package
+fn cpv(record: &PackageRecord) -> String {
+    format!("{}/{}-{}", record.category, record.package, record.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::mock;
+    use crate::emerge_mode::EmergeMode;
+    use crate::phase::Phase;
+    use crate::session::{EmergeSession, unix_now};
+    use std::{env, fs, process};
+    use tokio::net::UnixListener;
+
+    const THRESHOLD: Duration = Duration::from_secs(1800);
+
+    /// package that started `elapsed` ago and is still running
+    fn record(package: &str, elapsed: u64) -> PackageRecord {
+        PackageRecord {
+            category: String::from("dev-libs"),
+            package: String::from(package),
+            version: "1.0".parse().unwrap(),
+            repository: None,
+            start_time: unix_now() - Duration::from_secs(elapsed),
+            end_time: None,
+            estimated_end: None,
+            failed: None,
+            merged: false,
+        }
+    }
+
+    fn session(packages: Vec<PackageRecord>) -> EmergeSession {
+        let mut session = EmergeSession::new(Duration::from_secs(100), EmergeMode::Build);
+        session.packages = packages;
+        session
+    }
+
+    fn summaries(notifications: &[Notification]) -> Vec<&str> {
+        notifications.iter().map(|n| n.summary.as_str()).collect()
+    }
+
+    #[test]
+    fn failure() {
+        let last = ActiveJobs::from([(1, session(vec![record("foo", 60)]))]);
+        let mut now = last.clone();
+        now.get_mut(&1).unwrap().packages[0].failed = Some(Phase::Compile);
+
+        let notifications = changes(&last, &now, THRESHOLD);
+        assert_eq!(summaries(&notifications), ["Build failed"]);
+        assert_eq!(
+            notifications[0].body,
+            "dev-libs/foo-1.0 failed in the compile phase"
+        );
+        assert_eq!(notifications[0].urgency, URGENCY_CRITICAL);
+
+        // only once
+        assert!(changes(&now, &now, THRESHOLD).is_empty());
+    }
+
+    #[test]
+    fn long_build() {
+        let last =
+            ActiveJobs::from([(1, session(vec![record("short", 60), record("long", 7200)]))]);
+        let mut now = last.clone();
+        for record in &mut now.get_mut(&1).unwrap().packages {
+            record.merged = true;
+        }
+
+        let notifications = changes(&last, &now, THRESHOLD);
+        assert_eq!(summaries(&notifications), ["Build finished"]);
+        assert!(
+            notifications[0]
+                .body
+                .starts_with("dev-libs/long-1.0 merged after ")
+        );
+    }
+
+    #[test]
+    fn session_finished() {
+        let mut foo = record("foo", 60);
+        foo.merged = true;
+        let last = ActiveJobs::from([(1, session(vec![foo, record("bar", 30)]))]);
+        let mut now = last.clone();
+        let finished = now.get_mut(&1).unwrap();
+        finished.packages[1].failed = Some(Phase::Install);
+        finished.finish();
+
+        let notifications = changes(&last, &now, THRESHOLD);
+        assert_eq!(
+            summaries(&notifications),
+            ["Build failed", "Emerge finished"]
+        );
+        assert!(
+            notifications[1]
+                .body
+                .starts_with("1 merged, 1 failed after ")
+        );
+        assert_eq!(notifications[1].icon, "dialog-warning");
+
+        // a session seen for the first time was running before
+        assert!(changes(&ActiveJobs::new(), &now, THRESHOLD).is_empty());
+    }
+
+    #[test]
+    fn session_vanished() {
+        let mut foo = record("foo", 60);
+        foo.merged = true;
+        let last = ActiveJobs::from([(1, session(vec![foo])), (2, session(Vec::new()))]);
+
+        let notifications = changes(&last, &ActiveJobs::new(), THRESHOLD);
+        assert_eq!(summaries(&notifications), ["Emerge finished"]);
+        assert!(
+            notifications[0]
+                .body
+                .starts_with("1 merged, 0 failed after ")
+        );
+    }
+
+    #[tokio::test]
+    async fn mock_bus() {
+        let path = env::temp_dir().join(format!("portpresence-notify-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(mock::serve(listener, 2));
+
+        let mut sink = NotificationSink::new(&NotifyConfig::default());
+        let address = format!("unix:path={}", path.display());
+        sink.bus = Some(SessionBus::connect_to(&address).await.unwrap());
+        sink.send(&finished(3, 1, Duration::from_secs(754)))
+            .await
+            .unwrap();
+
+        let calls = server.await.unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let contains = |message: &[u8], text: &str| {
+            message
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+        };
+        assert!(contains(&calls[0], "Hello"));
+        for text in [
+            "org.freedesktop.Notifications",
+            "Notify",
+            "susssasa{sv}i",
+            "Emerge finished",
+            "3 merged, 1 failed after 12m 34s",
+            "dialog-warning",
+            "urgency",
+        ] {
+            assert!(contains(&calls[1], text), "{}", text);
+        }
+    }
+}
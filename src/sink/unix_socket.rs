@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::time::{Duration, timeout};
+
+use crate::api::remove_stale_socket;
+use crate::sink::PresenceSink;
+use crate::status::Status;
+use crate::watcher::ActiveJobs;
+
+/// lines queued for a client before it counts as stuck and is dropped
+const CLIENT_QUEUE_LEN: usize = 16;
+
+/// longest a write may block before the client is given up on
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// queues of the client writer tasks and the last line sent to them
+/// new clients get the last line right away
+#[derive(Default)]
+struct Clients {
+    queues: Vec<Sender<String>>,
+    last: Option<String>,
+}
+
+/// write queued lines to a client until it goes away or is dropped
+/// each client gets its own task so one that doesn't read can't block the others
+async fn write_client(mut stream: UnixStream, mut queue: Receiver<String>) {
+    while let Some(line) = queue.recv().await {
+        match timeout(CLIENT_WRITE_TIMEOUT, stream.write_all(line.as_bytes())).await {
+            Ok(Ok(())) => (),
+            _ => return,
+        }
+    }
+}
+
+/// broadcasts one JSON status object per update to everyone
+/// connected to a unix socket
+pub(crate) struct UnixSocketSink {
+    /// socket path
+    path: PathBuf,
+
+    /// shared with the accept task
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl UnixSocketSink {
+    /// the socket is created on connect
+    pub(crate) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            clients: Arc::new(Mutex::new(Clients::default())),
+        }
+    }
+}
+
+impl PresenceSink for UnixSocketSink {
+    fn name(&self) -> &'static str {
+        "unix socket broadcaster"
+    }
+
+    async fn connect(&mut self) -> Result<(), String> {
+        remove_stale_socket(&self.path)?;
+
+        let listener = UnixListener::bind(&self.path)
+            .map_err(|e| format!("Could not bind {}: {}", self.path.display(), e))?;
+
+        let clients = self.clients.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        eprintln!("Error accepting status client: {}", e);
+                        continue;
+                    }
+                };
+
+                let (queue, rx) = mpsc::channel(CLIENT_QUEUE_LEN);
+                tokio::spawn(write_client(stream, rx));
+
+                let mut clients = clients.lock().await;
+                if let Some(ref last) = clients.last {
+                    let _ = queue.try_send(last.clone());
+                }
+                clients.queues.push(queue);
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn update(&mut self, job_trees: &ActiveJobs) -> Result<(), String> {
+        let mut line = serde_json::to_string(&Status::new(job_trees)).map_err(|e| e.to_string())?;
+        line.push('\n');
+
+        let mut clients = self.clients.lock().await;
+
+        // drop everyone who disconnected or stopped reading
+        clients
+            .queues
+            .retain(|queue| queue.try_send(line.clone()).is_ok());
+        clients.last = Some(line);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,261 @@
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::emerge_mode::EmergeMode;
+use crate::fetch::FetchJob;
+use crate::phase::Phase;
+use crate::session::{BuildFailure, EmergeSession, PackageRecord, unix_now};
+use crate::watcher::{ActiveJobs, EbuildJob};
+
+/// serializable snapshot of the watcher state
+/// all times are unix timestamps in seconds
+#[derive(Serialize)]
+pub(crate) struct Status {
+    /// when this snapshot was taken
+    pub(crate) time: u64,
+
+    /// running emerge sessions, oldest first
+    pub(crate) sessions: Vec<SessionStatus>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SessionStatus {
+    /// emerge master pid
+    pub(crate) pid: u32,
+    pub(crate) mode: EmergeMode,
+
+    /// ebuild file of an `ebuild` command
+    pub(crate) ebuild: Option<PathBuf>,
+
+    /// still calculating dependencies
+    pub(crate) resolving: bool,
+    pub(crate) start_time: u64,
+    pub(crate) elapsed: u64,
+    pub(crate) progress: Option<ProgressStatus>,
+    pub(crate) estimated_end: Option<u64>,
+    pub(crate) jobs: Vec<JobStatus>,
+    pub(crate) fetches: Vec<FetchStatus>,
+    pub(crate) packages: Vec<PackageStatus>,
+
+    /// last build failure while it's shown
+    pub(crate) failure: Option<FailureStatus>,
+
+    /// what the session did, after emerge exited
+    pub(crate) summary: Option<SummaryStatus>,
+
+    /// emerge exited, the session is only kept for its failure or summary
+    pub(crate) finished: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ProgressStatus {
+    pub(crate) current: u32,
+    pub(crate) total: u32,
+}
+
+#[derive(Serialize)]
+pub(crate) struct JobStatus {
+    /// sandbox pid
+    pub(crate) pid: u32,
+    pub(crate) category: String,
+    pub(crate) package: String,
+    pub(crate) version: String,
+    pub(crate) repository: Option<String>,
+    pub(crate) phase: Phase,
+
+    /// human readable phase
+    pub(crate) phase_label: String,
+
+    /// merged from a binary package
+    pub(crate) binary: bool,
+
+    /// last line of the build log
+    pub(crate) last_line: Option<String>,
+
+    /// percentage the build system reported last
+    pub(crate) build_progress: Option<u8>,
+    pub(crate) start_time: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FetchStatus {
+    /// fetcher pid
+    pub(crate) pid: u32,
+    pub(crate) file: String,
+
+    /// bytes downloaded so far
+    pub(crate) size: u64,
+
+    /// distfile size from the Manifest
+    pub(crate) total: Option<u64>,
+
+    /// bytes per second
+    pub(crate) speed: Option<u64>,
+    pub(crate) start_time: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FailureStatus {
+    pub(crate) category: String,
+    pub(crate) package: String,
+    pub(crate) version: String,
+    pub(crate) repository: Option<String>,
+
+    /// phase that failed
+    pub(crate) phase: Phase,
+    pub(crate) time: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SummaryStatus {
+    /// packages emerge completed
+    pub(crate) merged: usize,
+
+    /// packages that failed
+    pub(crate) failed: usize,
+
+    /// wall time in seconds
+    pub(crate) elapsed: u64,
+
+    /// when emerge exited
+    pub(crate) end_time: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct PackageStatus {
+    pub(crate) category: String,
+    pub(crate) package: String,
+    pub(crate) version: String,
+    pub(crate) repository: Option<String>,
+    pub(crate) start_time: u64,
+    pub(crate) end_time: Option<u64>,
+    pub(crate) estimated_end: Option<u64>,
+
+    /// phase the package failed in
+    pub(crate) failed: Option<Phase>,
+}
+
+impl Status {
+    /// convert the watcher state
+    pub(crate) fn new(job_trees: &ActiveJobs) -> Self {
+        let mut sessions: Vec<SessionStatus> = job_trees
+            .iter()
+            .map(|(pid, session)| SessionStatus::new(*pid, session))
+            .collect();
+        sessions.sort_by_key(|session| (session.start_time, session.pid));
+
+        Self {
+            time: unix_now().as_secs(),
+            sessions,
+        }
+    }
+}
+
+impl SessionStatus {
+    fn new(pid: u32, session: &EmergeSession) -> Self {
+        // emerge does the merge step itself
+        let mut jobs: Vec<JobStatus> = session
+            .jobs
+            .iter()
+            .map(|(pid, job)| JobStatus::new(*pid, job))
+            .chain(session.merge_job().map(|job| JobStatus::new(pid, job)))
+            .collect();
+        jobs.sort_by_key(|job| job.pid);
+
+        let mut fetches: Vec<FetchStatus> = session
+            .fetches
+            .iter()
+            .map(|(pid, fetch)| FetchStatus::new(*pid, fetch))
+            .collect();
+        fetches.sort_by_key(|fetch| fetch.pid);
+
+        Self {
+            pid,
+            mode: session.mode,
+            ebuild: session.ebuild.clone(),
+            resolving: session.is_resolving(),
+            start_time: session.start_time.as_secs(),
+            elapsed: session.elapsed().as_secs(),
+            progress: session.progress.map(|progress| ProgressStatus {
+                current: progress.current,
+                total: progress.total,
+            }),
+            estimated_end: session.estimated_end.map(|time| time.as_secs()),
+            jobs,
+            fetches,
+            packages: session.packages.iter().map(PackageStatus::new).collect(),
+            failure: session.failure.as_ref().map(FailureStatus::new),
+            summary: session.summary.as_ref().map(|summary| SummaryStatus {
+                merged: summary.merged,
+                failed: summary.failed,
+                elapsed: summary.elapsed.as_secs(),
+                end_time: summary.time.as_secs(),
+            }),
+            finished: session.finished,
+        }
+    }
+}
+
+impl JobStatus {
+    fn new(pid: u32, job: &EbuildJob) -> Self {
+        Self {
+            pid,
+            category: job.category.clone(),
+            package: job.package.clone(),
+            version: job.version.to_string(),
+            repository: job.repository.clone(),
+            phase: job.phase.clone(),
+            phase_label: String::from(job.phase.label()),
+            binary: job.binary,
+            last_line: job
+                .build_log
+                .as_ref()
+                .and_then(|log| log.last_line())
+                .map(String::from),
+            build_progress: job.build_log.as_ref().and_then(|log| log.progress()),
+            start_time: job.create_time.as_secs(),
+        }
+    }
+}
+
+impl FetchStatus {
+    fn new(pid: u32, fetch: &FetchJob) -> Self {
+        Self {
+            pid,
+            file: fetch.file.clone(),
+            size: fetch.size,
+            total: fetch.total,
+            speed: fetch.speed,
+            start_time: fetch.create_time.as_secs(),
+        }
+    }
+}
+
+impl FailureStatus {
+    fn new(failure: &BuildFailure) -> Self {
+        let job = &failure.job;
+        Self {
+            category: job.category.clone(),
+            package: job.package.clone(),
+            version: job.version.to_string(),
+            repository: job.repository.clone(),
+            phase: job.phase.clone(),
+            time: failure.time.as_secs(),
+        }
+    }
+}
+
+impl PackageStatus {
+    fn new(record: &PackageRecord) -> Self {
+        Self {
+            category: record.category.clone(),
+            package: record.package.clone(),
+            version: record.version.to_string(),
+            repository: record.repository.clone(),
+            start_time: record.start_time.as_secs(),
+            end_time: record.end_time.map(|time| time.as_secs()),
+            estimated_end: record.estimated_end.map(|time| time.as_secs()),
+            failed: record.failed.clone(),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::emerge_mode::EmergeMode;
+use crate::fetch::{FetchJob, format_size};
+use crate::privacy::Visibility;
+use crate::session::{MergeProgress, SessionSummary, format_duration};
+use crate::watcher::EbuildJob;
+
+/// values available to templates
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Category,
+    Package,
+    Version,
+    Phase,
+    PhaseLabel,
+    Repo,
+    Root,
+    JobCount,
+    Phases,
+    Progress,
+    Elapsed,
+    Mode,
+    File,
+    FetchProgress,
+    FetchSpeed,
+    Ebuild,
+    LastLine,
+    BuildProgress,
+    Merged,
+    Failed,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 20] = [
+        Placeholder::Category,
+        Placeholder::Package,
+        Placeholder::Version,
+        Placeholder::Phase,
+        Placeholder::PhaseLabel,
+        Placeholder::Repo,
+        Placeholder::Root,
+        Placeholder::JobCount,
+        Placeholder::Phases,
+        Placeholder::Progress,
+        Placeholder::Elapsed,
+        Placeholder::Mode,
+        Placeholder::File,
+        Placeholder::FetchProgress,
+        Placeholder::FetchSpeed,
+        Placeholder::Ebuild,
+        Placeholder::LastLine,
+        Placeholder::BuildProgress,
+        Placeholder::Merged,
+        Placeholder::Failed,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Placeholder::Category => "category",
+            Placeholder::Package => "package",
+            Placeholder::Version => "version",
+            Placeholder::Phase => "phase",
+            Placeholder::PhaseLabel => "phase_label",
+            Placeholder::Repo => "repo",
+            Placeholder::Root => "root",
+            Placeholder::JobCount => "job_count",
+            Placeholder::Phases => "phases",
+            Placeholder::Progress => "progress",
+            Placeholder::Elapsed => "elapsed",
+            Placeholder::Mode => "mode",
+            Placeholder::File => "file",
+            Placeholder::FetchProgress => "fetch_progress",
+            Placeholder::FetchSpeed => "fetch_speed",
+            Placeholder::Ebuild => "ebuild",
+            Placeholder::LastLine => "last_line",
+            Placeholder::BuildProgress => "build_progress",
+            Placeholder::Merged => "merged",
+            Placeholder::Failed => "failed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// presence text like `Phase: {phase}`
+/// `{{` and `}}` are literal braces
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct Template {
+    /// source text, kept for Display
+    source: String,
+    segments: Vec<Segment>,
+}
+
+/// what templates are rendered from
+#[derive(Default)]
+pub(crate) struct Context<'a> {
+    /// the job for single-job templates
+    pub(crate) job: Option<&'a EbuildJob>,
+
+    /// the download for fetch templates
+    pub(crate) fetch: Option<&'a FetchJob>,
+
+    /// which job or download fields may be rendered
+    pub(crate) visibility: Visibility,
+
+    /// number of running jobs
+    pub(crate) job_count: usize,
+
+    /// running phases with counts like `compile (2), install (1)`
+    pub(crate) phases: String,
+
+    /// merge list position
+    pub(crate) progress: Option<MergeProgress>,
+
+    /// time since the oldest emerge session started
+    pub(crate) elapsed: Option<Duration>,
+
+    /// what emerge is doing
+    pub(crate) mode: EmergeMode,
+
+    /// ROOT packages are merged into
+    pub(crate) root: &'a str,
+
+    /// ebuild file of an `ebuild` command
+    pub(crate) ebuild: Option<&'a Path>,
+
+    /// what a finished emerge did
+    pub(crate) summary: Option<&'a SessionSummary>,
+}
+
+impl Template {
+    /// parse and check for unknown placeholders
+    pub(crate) fn parse(source: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{ in \"{}\"", source)),
+                        }
+                    }
+                    let placeholder = Placeholder::from_name(&name).ok_or_else(|| {
+                        let known: Vec<&str> =
+                            Placeholder::ALL.iter().map(|p| p.as_str()).collect();
+                        format!(
+                            "unknown placeholder {{{}}} in \"{}\", known are {}",
+                            name,
+                            source,
+                            known.join(", ")
+                        )
+                    })?;
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => return Err(format!("unmatched }} in \"{}\"", source)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Self {
+            source: String::from(source),
+            segments,
+        })
+    }
+
+    /// fill in the placeholders, missing values render empty
+    pub(crate) fn render(&self, context: &Context) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Placeholder(placeholder) => {
+                    out.push_str(&render_placeholder(*placeholder, context))
+                }
+            }
+        }
+        out
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn render_placeholder(placeholder: Placeholder, context: &Context) -> String {
+    let job = context.job;
+    // hidden fields render empty even in custom templates
+    let category = job.filter(|_| context.visibility != Visibility::Anonymous);
+    let package = job.filter(|_| context.visibility == Visibility::Show);
+    match placeholder {
+        Placeholder::Category => category.map(|job| job.category.clone()).unwrap_or_default(),
+        Placeholder::Package => package.map(|job| job.package.clone()).unwrap_or_default(),
+        Placeholder::Version => package
+            .map(|job| job.version.to_string())
+            .unwrap_or_default(),
+        Placeholder::Phase => job.map(|job| job.phase.to_string()).unwrap_or_default(),
+        Placeholder::PhaseLabel => job
+            .map(|job| String::from(job.phase.label()))
+            .unwrap_or_default(),
+        Placeholder::Repo => category
+            .and_then(|job| job.repository.clone())
+            .unwrap_or_default(),
+        Placeholder::Root => String::from(context.root),
+        Placeholder::JobCount => context.job_count.to_string(),
+        Placeholder::Phases => context.phases.clone(),
+        Placeholder::Progress => context
+            .progress
+            .map(|progress| format!("{} of {}", progress.current, progress.total))
+            .unwrap_or_default(),
+        Placeholder::Elapsed => context.elapsed.map(format_duration).unwrap_or_default(),
+        Placeholder::Mode => String::from(context.mode.label()),
+        Placeholder::File => context
+            .fetch
+            .filter(|_| context.visibility == Visibility::Show)
+            .map(|fetch| fetch.file.clone())
+            .unwrap_or_default(),
+        Placeholder::FetchProgress => context
+            .fetch
+            .map(|fetch| fetch.progress())
+            .unwrap_or_default(),
+        Placeholder::FetchSpeed => context
+            .fetch
+            .and_then(|fetch| fetch.speed)
+            .map(|speed| format!("{}/s", format_size(speed)))
+            .unwrap_or_default(),
+        Placeholder::Ebuild => context
+            .ebuild
+            .filter(|_| context.visibility == Visibility::Show)
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+        // build output can name anything, like paths and package names
+        Placeholder::LastLine => package
+            .and_then(|job| job.build_log.as_ref())
+            .and_then(|log| log.last_line())
+            .map(String::from)
+            .unwrap_or_default(),
+        Placeholder::BuildProgress => job
+            .and_then(|job| job.build_log.as_ref())
+            .and_then(|log| log.progress())
+            .map(|percent| format!("{}%", percent))
+            .unwrap_or_default(),
+        Placeholder::Merged => context
+            .summary
+            .map(|summary| summary.merged.to_string())
+            .unwrap_or_default(),
+        Placeholder::Failed => context
+            .summary
+            .map(|summary| summary.failed.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_only() {
+        let t = Template::parse("No Jobs Running").unwrap();
+        assert_eq!(t.render(&Context::default()), "No Jobs Running");
+    }
+
+    #[test]
+    fn placeholders() {
+        let t = Template::parse("{job_count} jobs, package {progress}").unwrap();
+        let context = Context {
+            job_count: 3,
+            progress: Some(MergeProgress {
+                current: 12,
+                total: 87,
+            }),
+            ..Context::default()
+        };
+        assert_eq!(t.render(&context), "3 jobs, package 12 of 87");
+    }
+
+    #[test]
+    fn summary() {
+        let t = Template::parse("{merged} merged, {failed} failed after {elapsed}").unwrap();
+        let summary = SessionSummary {
+            merged: 12,
+            failed: 1,
+            elapsed: Duration::from_secs(3723),
+            time: Duration::from_secs(1718000000),
+        };
+        let context = Context {
+            elapsed: Some(summary.elapsed),
+            summary: Some(&summary),
+            ..Context::default()
+        };
+        assert_eq!(t.render(&context), "12 merged, 1 failed after 1h 02m 03s");
+        assert_eq!(t.render(&Context::default()), " merged,  failed after ");
+    }
+
+    #[test]
+    fn missing_values_render_empty() {
+        let t = Template::parse("[{phase}]").unwrap();
+        assert_eq!(t.render(&Context::default()), "[]");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let t = Template::parse("{{{job_count}}}").unwrap();
+        let context = Context {
+            job_count: 2,
+            ..Context::default()
+        };
+        assert_eq!(t.render(&context), "{2}");
+    }
+
+    #[test]
+    fn invalid() {
+        for s in ["{nope}", "Phase: {phase", "Phase}", "{}"] {
+            assert!(Template::parse(s).is_err(), "{} should not parse", s);
+        }
+    }
+}
@@ -2,14 +2,31 @@ use procfs::{Current, Uptime};
 use psutil::Pid;
 use psutil::process::{Process, ProcessCollector};
 use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::mpsc::Sender;
-use tokio::time::{Duration, sleep};
+use tokio::sync::watch::Sender;
+use tokio::time::{Duration, Instant, sleep};
 
-use crate::REFRESH_INTERVAL_ACTIVE;
-use crate::REFRESH_INTERVAL_WAITING;
+use crate::atom::{Atom, Version};
+use crate::build_log::{BuildLog, build_repository, is_binary_build};
+use crate::config::{Backend, WatcherConfig};
+use crate::emerge_log::{EmergeLogTailer, LogEvent};
+use crate::emerge_mode::EmergeMode;
+use crate::fetch::{FetchJob, is_fetcher};
+use crate::fswatch::BuildDirWatcher;
+use crate::history::BuildHistory;
+use crate::phase::{Phase, parse_title, script_phase};
+use crate::proc_connector::{ProcConnector, ProcEvent};
+use crate::session::{EmergeSession, unix_now};
 
-pub(crate) type ActiveJobs = HashMap<Pid, HashMap<Pid, EbuildJob>>;
+/// emerge.log lines we keep around until their session shows up
+const MAX_PENDING_EVENTS: usize = 32;
+
+/// full rescan interval with inotify in case we missed something
+/// e.g. an emerge that exits without writing to emerge.log
+const INOTIFY_RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) type ActiveJobs = HashMap<Pid, EmergeSession>;
 
 /// job metadata
 #[derive(Clone)]
@@ -21,338 +38,967 @@ pub(crate) struct EbuildJob {
     pub(crate) package: String,
 
     /// ebuild version
-    pub(crate) version: String,
+    pub(crate) version: Version,
+
+    /// repository the ebuild comes from, if the environment or build dir was readable
+    pub(crate) repository: Option<String>,
 
     /// ebuild phase
-    pub(crate) phase: String,
+    pub(crate) phase: Phase,
+
+    /// merged from a binary package instead of built from source
+    pub(crate) binary: bool,
+
+    /// temp/build.log in the build dir, None for the merge step
+    pub(crate) build_log: Option<BuildLog>,
 
     /// job process creation time in unix epoch duration
     /// because each phase start a new sandbox process
-    /// this will reset with each phase
-    /// TODO: maybe walk further up the proc tree and match `emerge` process
+    /// this will reset with each phase, see EmergeSession
+    /// for times spanning the whole package or session
     pub(crate) create_time: Duration,
 }
 
 impl PartialEq<EbuildJob> for EbuildJob {
-    /// a job is equal if everything but create_time matches
+    /// a job is equal if everything but create_time and build_log matches
     /// create_time seems to vary slightly even with the same job
+    /// and the build log is sampled separately
     fn eq(&self, other: &EbuildJob) -> bool {
         self.category == other.category
             && self.package == other.package
             && self.version == other.version
+            && self.repository == other.repository
             && self.phase == other.phase
+            && self.binary == other.binary
     }
 }
 
+/// what the process matchers need from the config
+struct TrackOptions {
+    /// whether `ebuild` commands without emerge get their own session
+    ebuild_sessions: bool,
+
+    /// PORTAGE_TMPDIR, build dirs live in $PORTAGE_TMPDIR/portage
+    portage_tmpdir: PathBuf,
+
+    /// how long a build failure stays in the table
+    failure_grace_period: Duration,
+
+    /// how long a finished session stays in the table for its summary
+    summary_duration: Duration,
+}
+
 /// struct for tracking ebuild processes
 pub(crate) struct EbuildProcWatcher {
-    /// active jobs as: {"emerge master pid": {"ebuild job pid": {job...}}}
+    /// active sessions as: {"emerge master pid": {session...}}
     /// HashMap ensures we don't capture jobs multiple times
     active: ActiveJobs,
 
     /// sender for updates
     tx: Sender<ActiveJobs>,
+
+    /// refresh interval while no emerge is running
+    refresh_interval_waiting: Duration,
+
+    /// refresh interval while emerge is running
+    refresh_interval_active: Duration,
+
+    /// detection backend
+    backend: Backend,
+
+    /// how processes are matched, PORTAGE_TMPDIR is also watched with inotify
+    options: TrackOptions,
+
+    /// emerge log to watch with inotify
+    emerge_log: PathBuf,
+
+    /// reader for merge progress in emerge.log
+    log_tailer: EmergeLogTailer,
+
+    /// emerge.log lines not yet matched to a session
+    pending_events: Vec<LogEvent>,
+
+    /// when the watcher was created, older log lines are mostly history
+    /// and dropping them is expected
+    start_time: Duration,
+
+    /// past build durations for estimates
+    history: BuildHistory,
 }
 
 impl EbuildProcWatcher {
     /// create new EmergeProcWatcher
-    pub(crate) fn new(tx: Sender<ActiveJobs>) -> Self {
+    pub(crate) fn new(tx: Sender<ActiveJobs>, config: &WatcherConfig) -> Self {
         Self {
             active: HashMap::new(),
             tx,
+            refresh_interval_waiting: Duration::from_secs(config.refresh_interval_waiting),
+            refresh_interval_active: Duration::from_secs(config.refresh_interval_active),
+            backend: config.backend,
+            options: TrackOptions {
+                ebuild_sessions: config.ebuild_sessions,
+                portage_tmpdir: config.portage_tmpdir.clone(),
+                failure_grace_period: Duration::from_secs(config.failure_grace_period),
+                summary_duration: Duration::from_secs(config.summary_duration),
+            },
+            emerge_log: config.emerge_log.clone(),
+            log_tailer: EmergeLogTailer::new(&config.emerge_log),
+            pending_events: Vec::new(),
+            start_time: unix_now(),
+            history: BuildHistory::default(),
         }
     }
 
     /// continuesly watch processes for matches
     /// and update active table
     pub(crate) async fn start(mut self) -> Result<(), String> {
+        if self.backend == Backend::Netlink {
+            match ProcConnector::new() {
+                Ok(connector) => self.watch_proc_connector(connector).await?,
+                Err(e) => eprintln!("Error subscribing to proc connector: {}", e),
+            }
+            eprintln!("Falling back to polling");
+        }
+
         // if this fails we want the panic
         let mut collector = ProcessCollector::new().unwrap();
 
+        // event source for the inotify backend, None means polling
+        let mut fswatch = match self.backend {
+            Backend::Poll | Backend::Netlink => None,
+            Backend::Inotify => {
+                match BuildDirWatcher::new(&self.options.portage_tmpdir, &self.emerge_log) {
+                    Ok(fswatch) => Some(fswatch),
+                    Err(e) => {
+                        eprintln!("Error setting up inotify: {}", e);
+                        eprintln!("Falling back to polling");
+                        None
+                    }
+                }
+            }
+        };
+
         // interval between checks, will be set to actual value later
         let mut refresh_interval = Duration::from_secs(0);
 
+        // inotify only asks for a rescan after build dir activity
+        let mut rescan = true;
+        let mut last_rescan = Instant::now();
+
         loop {
             sleep(refresh_interval).await;
-            if let Err(e) = collector.update() {
-                eprintln!("Error updating processes: {}", e);
-                continue;
+
+            let mut changed = match rescan {
+                true => {
+                    if let Err(e) = collector.update() {
+                        eprintln!("Error updating processes: {}", e);
+                        continue;
+                    }
+                    last_rescan = Instant::now();
+
+                    // emerge logs the last package right before it exits
+                    // so read that before the session ends with a summary
+                    let mut changed = self.update_from_log();
+                    changed |= update_jobs(&mut self.active, &collector.processes, &self.options);
+                    changed |= self.update_from_log();
+                    changed
+                }
+                // only timers are due
+                false => expire_finished(&mut self.active, &self.options),
+            };
+            changed |= self.update_samples();
+
+            // increase poll rate while we have jobs to
+            // better capture phase changes
+            if !self.active.is_empty() {
+                refresh_interval = self.refresh_interval_active;
+            } else {
+                refresh_interval = self.refresh_interval_waiting;
             }
 
-            // track if we actually changed something
-            let mut changed = false;
+            self.send_update(changed).await?;
 
-            // remove finished jobs
-            let masters: Vec<Pid> = self.active.keys().cloned().collect();
-            for master in masters {
-                // first check if we can remove an entire subtree
-                if !&collector.processes.contains_key(&master)
-                    && self.active.remove(&master).is_some()
-                {
-                    #[cfg(debug_assertions)]
-                    eprintln!("Changed: subtree removed");
+            // with inotify we only rescan after build dir activity
+            // and the refresh interval just limits the rescan rate
+            if let Some(ref mut watcher) = fswatch {
+                refresh_interval = Duration::from_secs(0);
 
-                    changed = true;
-                    continue;
-                }
+                // downloads and build logs are sampled without a rescan
+                // and failures and summaries expire
+                let max_wait = match self.has_timers() {
+                    true => self.refresh_interval_active,
+                    false => INOTIFY_RESCAN_INTERVAL,
+                };
+                rescan = match watcher.wait(max_wait, self.refresh_interval_active).await {
+                    // new downloads don't touch the build dirs
+                    Ok(activity) => activity || last_rescan.elapsed() >= INOTIFY_RESCAN_INTERVAL,
+                    Err(e) => {
+                        eprintln!("Error watching build dirs: {}", e);
+                        eprintln!("Falling back to polling");
+                        fswatch = None;
+                        true
+                    }
+                };
+            }
+        }
+    }
 
-                // the check jobs under master
-                let jobs: Vec<Pid> = self.active.get(&master).unwrap().keys().cloned().collect();
-                for job in jobs {
-                    if !&collector.processes.contains_key(&job)
-                        && self.active.get_mut(&master).unwrap().remove(&job).is_some()
-                    {
-                        #[cfg(debug_assertions)]
-                        eprintln!("Changed: job removed");
+    /// track jobs from exec/exit events of the kernel proc connector
+    /// returns Ok if the connector broke and we should fall back
+    async fn watch_proc_connector(&mut self, connector: ProcConnector) -> Result<(), String> {
+        // pick up everything that's already running
+        let mut changed = match self.rescan() {
+            Ok(changed) => changed,
+            Err(e) => {
+                eprintln!("Error reading processes: {}", e);
+                return Ok(());
+            }
+        };
+
+        loop {
+            self.send_update(changed).await?;
 
-                        changed = true;
+            // downloads, build logs and expiring failures and summaries
+            // don't cause process events, so check them regularly
+            let events = match self.has_timers() {
+                true => tokio::select! {
+                    events = connector.recv() => events,
+                    _ = sleep(self.refresh_interval_active) => {
+                        changed = self.update_samples();
+                        changed |= expire_finished(&mut self.active, &self.options);
                         continue;
                     }
-                }
-            }
+                },
+                false => connector.recv().await,
+            };
 
-            // grab all running emerge processes and make sure they
-            // exist in our tree
-            let emerge_procs = get_emerge_procs(&collector.processes);
-            for process in emerge_procs {
-                if self.active.contains_key(&process.pid()) {
+            let events = match events {
+                Ok(events) => events,
+                // socket buffer overflowed and events got dropped
+                // so we can't trust our table anymore
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    eprintln!("Proc connector overflowed, rescanning");
+                    changed = match self.rescan() {
+                        Ok(changed) => changed,
+                        Err(e) => {
+                            eprintln!("Error reading processes: {}", e);
+                            return Ok(());
+                        }
+                    };
                     continue;
                 }
-                self.active.insert(process.pid(), HashMap::new());
-
-                #[cfg(debug_assertions)]
-                eprintln!("Changed: emerge master inserted");
-
-                changed = true;
-            }
+                Err(e) => {
+                    eprintln!("Error reading proc connector: {}", e);
+                    return Ok(());
+                }
+            };
 
-            // look for running ebuild processes
-            for (_pid, process) in &collector.processes {
-                let cmdline = match process.cmdline_vec() {
-                    Ok(maybe_cmdline) => match maybe_cmdline {
-                        Some(cmdline) => cmdline,
-                        None => continue, // kernel thread
+            // lines logged before an exit belong to the session it ends
+            changed = self.update_from_log();
+            for event in events {
+                let event_changed = match event {
+                    ProcEvent::Exec(pid) => match Process::new(pid) {
+                        Ok(process) => track_new_proc(&mut self.active, &process, &self.options),
+                        Err(_) => false, // exited already
                     },
-                    Err(_) => continue, // process died already
+                    ProcEvent::Exit(pid) => untrack_proc(&mut self.active, pid, &self.options),
                 };
+                changed |= event_changed;
+            }
+            changed |= self.update_from_log();
+        }
+    }
 
-                // now we look for any ebuild process like:
-                // bash /usr/lib/portage/pypy3.11/ebuild.sh unpack
-                if cmdline.len() != 3 {
-                    continue;
-                }
+    /// scan running processes once and return the detected jobs
+    pub(crate) fn snapshot(mut self) -> Result<ActiveJobs, String> {
+        self.rescan()?;
+        self.update_samples();
+        for session in self.active.values_mut() {
+            session.update_estimates(&self.history);
+        }
+        Ok(self.active)
+    }
 
-                // check if cmdline matches
-                if !cmdline[1].ends_with("ebuild.sh") {
-                    continue;
-                }
+    /// scan all processes and emerge.log once
+    /// returns whether the table changed
+    fn rescan(&mut self) -> Result<bool, String> {
+        let collector = ProcessCollector::new().map_err(|e| e.to_string())?;
+        let mut changed = self.update_from_log();
+        changed |= update_jobs(&mut self.active, &collector.processes, &self.options);
+        changed |= self.update_from_log();
+        Ok(changed)
+    }
 
-                #[cfg(debug_assertions)]
-                println!("Found ebuild process: {}", &_pid);
-
-                // gather infos by walking up the tree
-                let mut current = process.clone();
-                loop {
-                    // go up one layer
-                    current = match current.parent() {
-                        Ok(ps) => match ps {
-                            Some(ps) => ps,
-                            None => break, // parent dead
-                        },
-                        Err(_) => break, // current dead
-                    };
+    /// read new emerge.log lines into build history
+    /// and attach merge progress and merge steps to sessions
+    /// returns whether any session changed
+    fn update_from_log(&mut self) -> bool {
+        for event in self.log_tailer.read_new() {
+            self.history.record(&event);
+            self.pending_events.push(event);
+        }
 
-                    #[cfg(debug_assertions)]
-                    println!("Parsing parent process {}", current.pid());
-
-                    // cmdline_vec() doesn't help us because apparently
-                    // the sandbox likes to merge multiple args...
-                    let cmdline_str = match current.cmdline() {
-                        Ok(maybe_cmdline) => match maybe_cmdline {
-                            Some(cmdline) => cmdline,
-                            None => continue, // kernel thread
-                        },
-                        Err(_) => continue, // process died
-                    };
+        let mut changed = false;
+        self.pending_events
+            .retain(|event| match log_session(&self.active, event) {
+                Some(pid) => {
+                    if let Some(session) = self.active.get_mut(&pid) {
+                        changed |= session.apply_log(event);
+                    }
+                    false
+                }
+                None => true,
+            });
 
-                    let cmdline: Vec<&str> = cmdline_str.split_ascii_whitespace().collect();
-
-                    // we want a sandbox process like:
-                    // [sys-kernel/cachyos-kernel-6.15.1] sandbox /usr/lib/portage/pypy3.11/ebuild.sh compile
-                    if cmdline.len() == 4
-                        && cmdline[0].starts_with("[")
-                        && cmdline[0].ends_with("]")
-                        && cmdline[1] == "sandbox"
-                        && cmdline[2].ends_with("ebuild.sh")
-                    {
-                        #[cfg(debug_assertions)]
-                        println!("Process {} looks correct...", current.pid());
-
-                        // try to find master process, if that doesn't exist drop this job
-                        // this means we won't match manual `ebuild` invocations
-                        let master = match get_managing_emerge_proc(&current) {
-                            Some(ps) => ps,
-                            None => break,
-                        };
-
-                        let cpv = cmdline[0].trim_matches(['[', ']']);
-                        let (c, pv) = cpv.split_once('/').unwrap();
-
-                        let mut p = String::new();
-                        let mut v = String::new();
-                        let mut p_complete = false;
-                        for part in pv.split('-') {
-                            // start v on first number
-                            if part.starts_with(['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'])
-                            {
-                                p_complete = true;
-                            }
-
-                            if !p_complete {
-                                if !p.is_empty() {
-                                    p.push('-');
-                                }
-                                p.push_str(part);
-                            } else {
-                                if !v.is_empty() {
-                                    v.push('-');
-                                }
-                                v.push_str(part);
-                            }
-                        }
+        // drop lines that never matched
+        let excess = self.pending_events.len().saturating_sub(MAX_PENDING_EVENTS);
+        for event in self.pending_events.drain(..excess) {
+            if event.time() < self.start_time {
+                continue;
+            }
+            eprintln!(
+                "Dropping emerge.log line about {} that matched no emerge session",
+                event.atom()
+            );
+        }
 
-                        let new = EbuildJob {
-                            category: String::from(c),
-                            package: p,
-                            version: v,
-                            phase: String::from(cmdline[3]),
-                            create_time: proc_time_to_unix_time(current.create_time()),
-                        };
-
-                        match self.active.get(&master.pid()) {
-                            // full tree not present
-                            None => {
-                                self.active.insert(master.pid(), HashMap::new());
-                                self.active
-                                    .get_mut(&master.pid())
-                                    .unwrap()
-                                    .insert(current.pid(), new);
-
-                                #[cfg(debug_assertions)]
-                                eprintln!("Changed: tree inserted");
-
-                                changed = true;
-                            }
-                            Some(map) => match map.get(&current.pid()) {
-                                // job not present in tree
-                                None => {
-                                    self.active
-                                        .get_mut(&master.pid())
-                                        .unwrap()
-                                        .insert(current.pid(), new);
-
-                                    #[cfg(debug_assertions)]
-                                    eprintln!("Changed: job inserted");
-
-                                    changed = true;
-                                }
-                                Some(old) => {
-                                    // if jobs are equal we don't want an update
-                                    if new == old.clone() {
-                                        break;
-                                    }
-                                    // job present and new one different
-                                    self.active
-                                        .get_mut(&master.pid())
-                                        .unwrap()
-                                        .insert(current.pid(), new);
-
-                                    #[cfg(debug_assertions)]
-                                    eprintln!("Changed: job updated");
-
-                                    changed = true;
-                                }
-                            },
-                        }
+        changed
+    }
 
-                        break; // got all we need from this tree
-                    }
+    /// whether something changes without process events, like downloads,
+    /// build logs or failures and summaries waiting to expire
+    fn has_timers(&self) -> bool {
+        self.active.values().any(|session| {
+            !session.fetches.is_empty()
+                || session.jobs.values().any(|job| job.build_log.is_some())
+                || session.failure.is_some()
+                || session.summary.is_some()
+        })
+    }
+
+    /// read the progress of running downloads and the build logs of jobs
+    /// returns whether anything progressed
+    fn update_samples(&mut self) -> bool {
+        let mut changed = false;
+        for session in self.active.values_mut() {
+            for fetch in session.fetches.values_mut() {
+                changed |= fetch.sample();
+            }
+            for job in session.jobs.values_mut() {
+                if let Some(build_log) = &mut job.build_log {
+                    changed |= build_log.sample();
                 }
             }
+        }
+        changed
+    }
 
-            // increase poll rate while we have jobs to
-            // better capture phase changes
-            if !self.active.is_empty() {
-                refresh_interval = Duration::from_secs(REFRESH_INTERVAL_ACTIVE);
-            } else {
-                refresh_interval = Duration::from_secs(REFRESH_INTERVAL_WAITING);
+    /// send the job list if changed
+    async fn send_update(&mut self, changed: bool) -> Result<(), String> {
+        match changed {
+            false => {
+                #[cfg(debug_assertions)]
+                println!("Job list unchanged ({} items)", self.active.len());
             }
+            true => {
+                #[cfg(debug_assertions)]
+                println!("Job list updated ({} items)", self.active.len());
 
-            // send the job list if changed
-            match changed {
-                false => {
-                    #[cfg(debug_assertions)]
-                    println!("Job list unchanged ({} items)", self.active.len());
+                for session in self.active.values_mut() {
+                    session.update_estimates(&self.history);
                 }
-                true => {
-                    #[cfg(debug_assertions)]
-                    println!("Job list updated ({} items)", self.active.len());
 
-                    if self.tx.send(self.active.clone()).await.is_err() {
-                        return Err(String::from("Connection to RPC handler died"));
-                    }
+                if self.tx.send(self.active.clone()).is_err() {
+                    return Err(String::from("All presence sinks died"));
                 }
             }
         }
+
+        Ok(())
     }
 }
 
-/// convert process creation time to unix time
-fn proc_time_to_unix_time(proc_time: Duration) -> Duration {
-    let uptime = Uptime::current().unwrap().uptime_duration();
-    let currtime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    currtime - uptime + proc_time
+/// the session an emerge.log line belongs to
+/// the log has no pids, so this goes by package, then by the only session
+/// that could have written the line, then by the merge list for new packages
+/// `None` while it's ambiguous or the session hasn't shown up yet
+fn log_session(active: &ActiveJobs, event: &LogEvent) -> Option<Pid> {
+    let unmerge = matches!(
+        event,
+        LogEvent::Unmerging { .. } | LogEvent::Unmerged { .. }
+    );
+
+    // lines older than a session can't belong to it
+    // and finished sessions don't get new lines
+    // only merges and unmerges write to emerge.log
+    let candidates: Vec<(Pid, &EmergeSession)> = active
+        .iter()
+        .filter(|(_, session)| {
+            !session.finished
+                && event.time().as_secs() >= session.start_time.as_secs()
+                && (session.mode == EmergeMode::Build
+                    || unmerge && session.mode == EmergeMode::Remove)
+        })
+        .map(|(pid, session)| (*pid, session))
+        .collect();
+
+    // the one candidate that matches, if there is exactly one
+    let only = |matches: &dyn Fn(&EmergeSession) -> bool| {
+        let mut matching = candidates.iter().filter(|(_, session)| matches(session));
+        match (matching.next(), matching.next()) {
+            (Some((pid, _)), None) => Some(*pid),
+            _ => None,
+        }
+    };
+
+    // a package of the session or the one it merges,
+    // for unmerges the version being replaced
+    let atom = event.atom();
+    let claimed = only(&|session| {
+        session.contains(atom)
+            || session.merge.as_ref().is_some_and(|merge| {
+                merge.category == atom.category
+                    && merge.package == atom.package
+                    && (unmerge || merge.version == atom.version)
+            })
+    });
+    if claimed.is_some() {
+        return claimed;
+    }
+
+    if let [(pid, _)] = candidates[..] {
+        return Some(pid);
+    }
+
+    // the next package of a merge list that isn't done yet
+    let LogEvent::Started { progress, .. } = event else {
+        return None;
+    };
+    only(&|session| match session.progress {
+        Some(current) => current.total == progress.total && current.current < progress.current,
+        None => !session.started,
+    })
 }
 
-/// parse a process list for emerge processes
-fn get_emerge_procs(processes: &BTreeMap<Pid, Process>) -> Vec<Process> {
-    #[cfg(debug_assertions)]
-    println!("Looking for emerge processes");
+/// scan a process list once and update the job table
+/// returns whether the table changed
+fn update_jobs(
+    active: &mut ActiveJobs,
+    processes: &BTreeMap<Pid, Process>,
+    options: &TrackOptions,
+) -> bool {
+    // track if we actually changed something
+    let mut changed = false;
+
+    changed |= expire_finished(active, options);
+
+    // remove finished jobs
+    let masters: Vec<Pid> = active.keys().cloned().collect();
+    for master in masters {
+        // first check if we can remove an entire subtree
+        if !processes.contains_key(&master) {
+            changed |= end_session(active, master, options);
+            continue;
+        }
+
+        // the check jobs under master
+        let session = active.get_mut(&master).unwrap();
+        let jobs: Vec<Pid> = session
+            .jobs
+            .keys()
+            .chain(session.fetches.keys())
+            .cloned()
+            .collect();
+        for job in jobs {
+            if !processes.contains_key(&job) && session.remove_job(&job) {
+                #[cfg(debug_assertions)]
+                eprintln!("Changed: job removed");
+
+                changed = true;
+                continue;
+            }
+        }
+    }
+
+    // grab all running emerge processes and make sure they
+    // exist in our tree
+    let emerge_procs = get_emerge_procs(processes, options.ebuild_sessions);
+    for process in emerge_procs {
+        if is_tracked(active, process.pid()) {
+            continue;
+        }
+        active.insert(process.pid(), new_session(&process));
+
+        #[cfg(debug_assertions)]
+        eprintln!("Changed: emerge master inserted");
 
-    let mut emerge_procs = Vec::new();
+        changed = true;
+    }
+
+    // look for running ebuild and fetcher processes
     for process in processes.values() {
-        let cmdline_str = match process.cmdline() {
+        if track_ebuild_proc(active, process, options) || track_fetch_proc(active, process, options)
+        {
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+/// whether an emerge master still has its session
+/// the pid of a finished one may be reused by a new emerge
+fn is_tracked(active: &ActiveJobs, master: Pid) -> bool {
+    active.get(&master).is_some_and(|session| !session.finished)
+}
+
+/// the emerge master exited, drop its session
+/// unless a build failure or the summary should stay shown for a while
+/// returns whether the table changed
+fn end_session(active: &mut ActiveJobs, master: Pid, options: &TrackOptions) -> bool {
+    let Some(session) = active.get_mut(&master).filter(|session| !session.finished) else {
+        return false;
+    };
+
+    session.finish();
+    session.expire_failure(options.failure_grace_period);
+    session.expire_summary(options.summary_duration);
+    if !session.is_shown() {
+        active.remove(&master);
+
+        #[cfg(debug_assertions)]
+        eprintln!("Changed: subtree removed");
+    } else {
+        #[cfg(debug_assertions)]
+        eprintln!("Changed: subtree finished");
+    }
+
+    true
+}
+
+/// clear failures and summaries shown long enough
+/// and drop finished sessions with nothing left to show
+/// returns whether the table changed
+fn expire_finished(active: &mut ActiveJobs, options: &TrackOptions) -> bool {
+    let mut changed = false;
+    for session in active.values_mut() {
+        changed |= session.expire_failure(options.failure_grace_period);
+        changed |= session.expire_summary(options.summary_duration);
+    }
+    active.retain(|_, session| session.is_shown());
+    changed
+}
+
+/// record a freshly exec'd process if it's an emerge master or ebuild job
+/// returns whether the table changed
+fn track_new_proc(active: &mut ActiveJobs, process: &Process, options: &TrackOptions) -> bool {
+    if is_master(process, options.ebuild_sessions) {
+        if is_tracked(active, process.pid()) {
+            return false;
+        }
+        active.insert(process.pid(), new_session(process));
+
+        #[cfg(debug_assertions)]
+        eprintln!("Changed: emerge master inserted");
+
+        return true;
+    }
+
+    track_ebuild_proc(active, process, options) || track_fetch_proc(active, process, options)
+}
+
+/// drop an exited process from the table
+/// returns whether the table changed
+fn untrack_proc(active: &mut ActiveJobs, pid: Pid, options: &TrackOptions) -> bool {
+    if end_session(active, pid, options) {
+        return true;
+    }
+
+    for session in active.values_mut() {
+        if session.remove_job(&pid) {
+            #[cfg(debug_assertions)]
+            eprintln!("Changed: job removed");
+
+            return true;
+        }
+    }
+
+    false
+}
+
+/// check if a process is an ebuild.sh job and record it under its emerge master
+/// returns whether the table changed
+fn track_ebuild_proc(active: &mut ActiveJobs, process: &Process, options: &TrackOptions) -> bool {
+    // track if we actually changed something
+    let mut changed = false;
+
+    let cmdline = match process.cmdline_vec() {
+        Ok(maybe_cmdline) => match maybe_cmdline {
+            Some(cmdline) => cmdline,
+            None => return false, // kernel thread
+        },
+        Err(_) => return false, // process died already
+    };
+
+    // now we look for any ebuild process like:
+    // bash /usr/lib/portage/pypy3.11/ebuild.sh unpack
+    // bash /usr/lib/portage/pypy3.11/misc-functions.sh install_qa_check
+    let args: Vec<&str> = cmdline.iter().skip(1).map(String::as_str).collect();
+    let Some(phase) = script_phase(&args) else {
+        return false;
+    };
+
+    #[cfg(debug_assertions)]
+    println!("Found ebuild process: {}", process.pid());
+
+    // gather infos by walking up the tree
+    // the outermost script process is the job if there's no wrapper
+    let mut outermost = (process.clone(), phase);
+    let mut current = process.clone();
+    let (job_proc, cpv, phase) = loop {
+        // go up one layer
+        current = match current.parent() {
+            Ok(ps) => match ps {
+                Some(ps) => ps,
+                None => return false, // parent dead
+            },
+            Err(_) => return false, // current dead
+        };
+
+        #[cfg(debug_assertions)]
+        println!("Parsing parent process {}", current.pid());
+
+        // cmdline_vec() doesn't help us because apparently
+        // the sandbox likes to merge multiple args...
+        let cmdline_str = match current.cmdline() {
             Ok(maybe_cmdline) => match maybe_cmdline {
                 Some(cmdline) => cmdline,
                 None => continue, // kernel thread
             },
-            Err(_) => continue, // process died already
+            Err(_) => continue, // process died
         };
 
-        let cmdline: Vec<&str> = cmdline_str.split_ascii_whitespace().collect();
+        // we want a wrapper process like:
+        // [sys-kernel/cachyos-kernel-6.15.1] sandbox /usr/lib/portage/pypy3.11/ebuild.sh compile
+        if let Some((cpv, phase)) = parse_title(&cmdline_str) {
+            #[cfg(debug_assertions)]
+            println!("Process {} looks correct...", current.pid());
 
-        // now we look for any emerge process like:
-        // /usr/bin/pypy3.11 /usr/lib/python-exec/pypy3.11/emerge args...
-        if cmdline.len() < 2 {
-            continue;
+            break (current, String::from(cpv), phase);
         }
 
-        // check if cmdline matches
-        // leading "/" makes this not match e.g. sudo emerge
-        if cmdline[1].ends_with("/emerge") {
+        // phases outside the sandbox like pkg_postinst may run
+        // right under emerge after bash exec'd ebuild.sh
+        if is_emerge_proc(&current) || (options.ebuild_sessions && is_ebuild_proc(&current)) {
+            let (outermost, phase) = outermost;
+
+            // the title is gone, but the environment has the package
+            // if it's not readable, emerge.log tells which package is being merged
+            // pkg_prerm and pkg_postrm are left out, they run for the replaced version
+            let environ = proc_environ(outermost.pid());
+            let cpv = match (environ.get("CATEGORY"), environ.get("PF")) {
+                (Some(category), Some(pf)) => format!("{}/{}", category, pf),
+                _ if matches!(phase, Phase::Preinst | Phase::Postinst) => {
+                    match merging_package(active, &outermost, options) {
+                        Some(cpv) => cpv,
+                        None => return false,
+                    }
+                }
+                _ => return false,
+            };
+
+            #[cfg(debug_assertions)]
+            println!("Process {} runs {} unsandboxed", outermost.pid(), phase);
+
+            break (outermost, cpv, phase);
+        }
+
+        // ebuild.sh forks subshells with the same cmdline
+        let parent_args: Vec<&str> = cmdline_str.split_ascii_whitespace().skip(1).collect();
+        if let Some(phase) = script_phase(&parent_args) {
+            outermost = (current.clone(), phase);
+        }
+    };
+
+    // try to find master process, if that doesn't exist drop this job
+    // manual `ebuild` invocations are only matched if enabled
+    let Some(master) = get_managing_emerge_proc(&job_proc, options.ebuild_sessions) else {
+        return false;
+    };
+
+    let atom = match cpv.parse::<Atom>() {
+        Ok(atom) => atom,
+        Err(e) => {
+            eprintln!("Error parsing job {}: {}", cpv, e);
+            return false;
+        }
+    };
+
+    // portage tells the ebuild where the package comes from
+    let environ = proc_environ(process.pid());
+
+    // the default build dir if the environment isn't readable
+    let build_dir = match environ.get("PORTAGE_BUILDDIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => options.portage_tmpdir.join("portage").join(&cpv),
+    };
+
+    // otherwise emerge.log or the build dir tell binary packages apart
+    let binary = match environ.get("EMERGE_FROM") {
+        Some(from) => from == "binary",
+        None => {
+            active
+                .get(&master.pid())
+                .is_some_and(|session| session.is_merging_binary(&atom))
+                || is_binary_build(&build_dir)
+        }
+    };
+
+    let repository = match environ.get("PORTAGE_REPO_NAME") {
+        Some(repository) => Some(repository.clone()),
+        None => build_repository(&build_dir),
+    };
+
+    let new = EbuildJob {
+        category: atom.category,
+        package: atom.package,
+        version: atom.version,
+        repository,
+        phase,
+        binary,
+        build_log: Some(BuildLog::new(&build_dir)),
+        create_time: proc_time_to_unix_time(job_proc.create_time()),
+    };
+
+    let session = match active.get_mut(&master.pid()) {
+        Some(session) => session,
+        // full tree not present
+        None => {
             #[cfg(debug_assertions)]
-            println!("Found emerge process {}: {}", process.pid(), cmdline_str);
+            eprintln!("Changed: tree inserted");
 
-            emerge_procs.push(process.clone());
+            changed = true;
+            active
+                .entry(master.pid())
+                .or_insert_with(|| new_session(&master))
         }
+    };
+
+    if session.insert_job(job_proc.pid(), new) {
+        #[cfg(debug_assertions)]
+        eprintln!("Changed: job inserted or updated");
+
+        changed = true;
+    }
+
+    changed
+}
+
+/// check if a process is a wget/curl download and record it under its emerge master
+/// returns whether the table changed
+fn track_fetch_proc(active: &mut ActiveJobs, process: &Process, options: &TrackOptions) -> bool {
+    let cmdline = match process.cmdline_vec() {
+        Ok(Some(cmdline)) => cmdline,
+        _ => return false, // kernel thread or process died already
+    };
+    if !is_fetcher(&cmdline) {
+        return false;
     }
-    emerge_procs
+
+    // already known, progress is updated separately
+    if active
+        .values()
+        .any(|session| session.fetches.contains_key(&process.pid()))
+    {
+        return false;
+    }
+
+    // fetchers outside of emerge are none of our business
+    let Some(master) = get_managing_emerge_proc(process, options.ebuild_sessions) else {
+        return false;
+    };
+
+    // the environment has the package but may not be readable
+    let environ = proc_environ(process.pid());
+
+    let create_time = proc_time_to_unix_time(process.create_time());
+    let Some(fetch) = FetchJob::from_proc(&cmdline, &environ, create_time) else {
+        return false;
+    };
+
+    #[cfg(debug_assertions)]
+    println!("Found fetch process {}: {}", process.pid(), fetch.file);
+
+    active
+        .entry(master.pid())
+        .or_insert_with(|| new_session(&master))
+        .insert_fetch(process.pid(), fetch);
+    true
+}
+
+/// `cat/pkg-ver` emerge.log says the session of a process is merging
+fn merging_package(
+    active: &ActiveJobs,
+    process: &Process,
+    options: &TrackOptions,
+) -> Option<String> {
+    let master = get_managing_emerge_proc(process, options.ebuild_sessions)?;
+    let merge = active.get(&master.pid())?.merge.as_ref()?;
+    Some(format!(
+        "{}/{}-{}",
+        merge.category, merge.package, merge.version
+    ))
+}
+
+/// environment of a process, empty if it's not readable
+fn proc_environ(pid: Pid) -> HashMap<String, String> {
+    procfs::process::Process::new(pid as i32)
+        .and_then(|process| process.environ())
+        .map(|environ| {
+            environ
+                .into_iter()
+                .map(|(k, v)| {
+                    (
+                        k.to_string_lossy().into_owned(),
+                        v.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// start a session for an emerge or ebuild master process
+fn new_session(master: &Process) -> EmergeSession {
+    let start_time = proc_time_to_unix_time(master.create_time());
+
+    // same merged args issue as in is_emerge_proc
+    // skip interpreter and emerge itself
+    let cmdline = master.cmdline().ok().flatten().unwrap_or_default();
+    let args = cmdline.split_ascii_whitespace().skip(2);
+
+    if is_ebuild_proc(master) {
+        let mut session = EmergeSession::new(start_time, EmergeMode::Ebuild);
+
+        // relative to where `ebuild` was run
+        session.ebuild = args
+            .into_iter()
+            .find(|arg| arg.ends_with(".ebuild"))
+            .map(|path| match master.cwd() {
+                Ok(cwd) => cwd.join(path),
+                Err(_) => PathBuf::from(path),
+            });
+
+        #[cfg(debug_assertions)]
+        println!("Ebuild process {} runs {:?}", master.pid(), session.ebuild);
+
+        return session;
+    }
+
+    let mode = EmergeMode::from_args(args);
+
+    #[cfg(debug_assertions)]
+    println!("Emerge process {} is in {:?} mode", master.pid(), mode);
+
+    EmergeSession::new(start_time, mode)
+}
+
+/// convert process creation time to unix time
+fn proc_time_to_unix_time(proc_time: Duration) -> Duration {
+    let uptime = Uptime::current().unwrap().uptime_duration();
+    let currtime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    currtime - uptime + proc_time
+}
+
+/// parse a process list for emerge processes
+/// and `ebuild` processes if they get their own session
+fn get_emerge_procs(processes: &BTreeMap<Pid, Process>, ebuild_sessions: bool) -> Vec<Process> {
+    #[cfg(debug_assertions)]
+    println!("Looking for emerge processes");
+
+    processes
+        .values()
+        .filter(|process| is_master(process, ebuild_sessions))
+        .cloned()
+        .collect()
+}
+
+/// check if a process is an emerge process like:
+/// /usr/bin/pypy3.11 /usr/lib/python-exec/pypy3.11/emerge args...
+fn is_emerge_proc(process: &Process) -> bool {
+    is_portage_command(process, "/emerge")
+}
+
+/// check if a process is an ebuild process like:
+/// /usr/bin/python3.12 /usr/lib/python-exec/python3.12/ebuild foo-1.0.ebuild compile
+fn is_ebuild_proc(process: &Process) -> bool {
+    is_portage_command(process, "/ebuild")
+}
+
+/// check if a process runs the portage python script ending in `suffix`
+fn is_portage_command(process: &Process, suffix: &str) -> bool {
+    // cmdline_vec() doesn't help us because apparently
+    // the sandbox likes to merge multiple args...
+    let cmdline_str = match process.cmdline() {
+        Ok(maybe_cmdline) => match maybe_cmdline {
+            Some(cmdline) => cmdline,
+            None => return false, // kernel thread
+        },
+        Err(_) => return false, // process died already
+    };
+
+    let cmdline: Vec<&str> = cmdline_str.split_ascii_whitespace().collect();
+
+    if cmdline.len() < 2 {
+        return false;
+    }
+
+    // check if cmdline matches
+    // leading "/" makes this not match e.g. sudo emerge
+    if cmdline[1].ends_with(suffix) {
+        #[cfg(debug_assertions)]
+        println!(
+            "Found {} process {}: {}",
+            &suffix[1..],
+            process.pid(),
+            cmdline_str
+        );
+
+        return true;
+    }
+
+    false
+}
+
+/// check if a process is an emerge process that isn't a helper
+/// portage forks for fetching, binpkg extraction etc. and the
+/// forks keep the cmdline of their emerge master
+fn is_emerge_master(process: &Process) -> bool {
+    if !is_emerge_proc(process) {
+        return false;
+    }
+    !matches!(process.parent(), Ok(Some(parent)) if is_emerge_proc(&parent))
+}
+
+/// check if a process is an `ebuild` command run without emerge
+fn is_ebuild_master(process: &Process) -> bool {
+    if !is_ebuild_proc(process) {
+        return false;
+    }
+    !matches!(process.parent(), Ok(Some(parent)) if is_ebuild_proc(&parent) || is_emerge_proc(&parent))
+}
+
+/// check if a process gets its own session
+fn is_master(process: &Process, ebuild_sessions: bool) -> bool {
+    is_emerge_master(process) || (ebuild_sessions && is_ebuild_master(process))
 }
 
 /// get managing emerge process of process like
 /// /usr/bin/pypy3.11 /usr/lib/python-exec/pypy3.11/emerge args...
+/// or the `ebuild` process if they get their own session
 /// we will match the first one in case of e.g. `sudo emerge ..args`
-fn get_managing_emerge_proc(process: &Process) -> Option<Process> {
+/// skipping portage's own forks of it
+fn get_managing_emerge_proc(process: &Process, ebuild_sessions: bool) -> Option<Process> {
     #[cfg(debug_assertions)]
     println!("Looking for managing emerge for {}", process.pid());
 
@@ -367,27 +1013,118 @@ fn get_managing_emerge_proc(process: &Process) -> Option<Process> {
         #[cfg(debug_assertions)]
         println!("Parsing parent process {}", current.pid());
 
-        // cmdline_vec() doesn't help us because apparently
-        // the sandbox likes to merge multiple args...
-        let cmdline_str = match current.cmdline() {
-            Ok(maybe_cmdline) => match maybe_cmdline {
-                Some(cmdline) => cmdline,
-                None => continue, // kernel thread
-            },
-            Err(_) => continue, // process died
-        };
+        if is_master(&current, ebuild_sessions) {
+            return Some(current);
+        }
+    }
+}
 
-        let cmdline: Vec<&str> = cmdline_str.split_ascii_whitespace().collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emerge_log::parse_line;
 
-        if cmdline.len() < 2 {
-            continue;
+    fn session(mode: EmergeMode, lines: &[&str]) -> EmergeSession {
+        let mut session = EmergeSession::new(Duration::from_secs(1000), mode);
+        for line in lines {
+            session.apply_log(&parse_line(line).unwrap());
         }
+        session
+    }
 
-        if cmdline[1].ends_with("/emerge") {
-            #[cfg(debug_assertions)]
-            println!("Found emerge process {}: {}", current.pid(), cmdline_str);
+    /// a running ebuild process as the process scan would add it
+    fn job(cpv: &str) -> EbuildJob {
+        let atom: Atom = cpv.parse().unwrap();
+        EbuildJob {
+            category: atom.category,
+            package: atom.package,
+            version: atom.version,
+            repository: None,
+            phase: Phase::Compile,
+            binary: false,
+            build_log: None,
+            create_time: Duration::from_secs(1010),
+        }
+    }
 
-            return Some(current);
+    fn matched(active: &ActiveJobs, line: &str) -> Option<Pid> {
+        log_session(active, &parse_line(line).unwrap())
+    }
+
+    #[test]
+    fn parallel_sessions() {
+        let mut active = ActiveJobs::from([
+            (1, session(EmergeMode::Build, &[])),
+            (2, session(EmergeMode::Build, &[])),
+        ]);
+        // both could be starting their first package
+        assert_eq!(
+            matched(&active, "1005:  >>> emerge (1 of 3) dev-libs/foo-1.0 to /"),
+            None
+        );
+
+        active.insert(
+            1,
+            session(
+                EmergeMode::Build,
+                &[
+                    "1005:  >>> emerge (1 of 3) dev-libs/foo-1.0 to /",
+                    "1015:  === (1 of 3) Merging (dev-libs/foo-1.0::/x/foo-1.0.ebuild)",
+                ],
+            ),
+        );
+        let mut bar = session(
+            EmergeMode::Build,
+            &["1005:  >>> emerge (1 of 2) dev-libs/bar-1.0 to /"],
+        );
+        bar.insert_job(10, job("dev-libs/bar-1.0"));
+        active.insert(2, bar);
+        for (line, pid) in [
+            (
+                "1020:  ::: completed emerge (1 of 2) dev-libs/bar-1.0 to /",
+                2,
+            ),
+            ("1020:  === Unmerging... (dev-libs/foo-0.9)", 1),
+            ("1020:  >>> emerge (2 of 3) dev-libs/baz-1.0 to /", 1),
+            ("1020:  >>> emerge (2 of 2) dev-libs/qux-1.0 to /", 2),
+        ] {
+            assert_eq!(matched(&active, line), Some(pid), "{}", line);
         }
+
+        // older than both sessions
+        assert_eq!(
+            matched(
+                &active,
+                "900:  ::: completed emerge (1 of 2) dev-libs/bar-1.0 to /"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn only_build_session() {
+        let active = ActiveJobs::from([
+            (1, session(EmergeMode::Search, &[])),
+            (2, session(EmergeMode::Build, &[])),
+            (3, session(EmergeMode::Remove, &[])),
+        ]);
+        assert_eq!(
+            matched(&active, "1005:  >>> emerge (1 of 3) dev-libs/foo-1.0 to /"),
+            Some(2)
+        );
+        // --depclean and a build can both unmerge
+        assert_eq!(
+            matched(&active, "1005:  === Unmerging... (dev-libs/foo-0.9)"),
+            None
+        );
+
+        let active = ActiveJobs::from([
+            (1, session(EmergeMode::Search, &[])),
+            (3, session(EmergeMode::Remove, &[])),
+        ]);
+        assert_eq!(
+            matched(&active, "1005:  === Unmerging... (dev-libs/foo-0.9)"),
+            Some(3)
+        );
     }
 }